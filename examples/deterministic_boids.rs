@@ -18,18 +18,15 @@ use std::{
 
 use bevy::prelude::*;
 use bevy_quinnet::{
-    client::{
-        certificate::CertificateVerificationMode, connection::ClientAddrConfiguration,
-        ClientConnectionConfiguration, ClientConnectionConfigurationDefaultables, QuinnetClient,
-    },
-    server::{
-        certificate::CertificateRetrievalMode, EndpointAddrConfiguration, QuinnetServer,
-        ServerEndpointConfiguration, ServerEndpointConfigurationDefaultables,
-    },
+    client::{certificate::CertificateVerificationMode, connection::ClientAddrConfiguration},
+    server::{certificate::CertificateRetrievalMode, EndpointAddrConfiguration},
 };
 use bevy_replicon::prelude::*;
 
-use bevy_replicon_quinnet::{ChannelsConfigurationExt, RepliconQuinnetPlugins};
+use bevy_replicon_quinnet::{
+    client::RepliconQuinnetClientCommandsExt, server::RepliconQuinnetServerConfig,
+    RepliconQuinnetPlugins,
+};
 use clap::Parser;
 use fastrand::Rng;
 use fastrand_contrib::RngExt;
@@ -55,13 +52,7 @@ const MIN_SPEED: f32 = 3.0;
 const MAX_BIAS: f32 = 0.01;
 const BIAS_INCREMENT: f32 = 0.00004;
 
-fn setup(
-    mut commands: Commands,
-    cli: Res<Cli>,
-    channels: Res<RepliconChannels>,
-    mut server: ResMut<QuinnetServer>,
-    mut client: ResMut<QuinnetClient>,
-) -> Result<()> {
+fn setup(mut commands: Commands, cli: Res<Cli>) -> Result<()> {
     commands.spawn(Camera2d);
 
     match *cli {
@@ -73,20 +64,12 @@ fn setup(
             info!("starting server at port {port}");
 
             // Backend initialization
-            server
-                .start_endpoint(ServerEndpointConfiguration {
-                    addr_config: EndpointAddrConfiguration::from_ip(
-                        IpAddr::V6(Ipv6Addr::LOCALHOST),
-                        port,
-                    ),
-                    cert_mode: CertificateRetrievalMode::GenerateSelfSigned {
-                        server_hostname: Ipv6Addr::LOCALHOST.to_string(),
-                    },
-                    defaultables: ServerEndpointConfigurationDefaultables {
-                        send_channels_cfg: channels.server_configs(),
-                    },
-                })
-                .unwrap();
+            commands.insert_resource(RepliconQuinnetServerConfig::new(
+                EndpointAddrConfiguration::from_ip(IpAddr::V6(Ipv6Addr::LOCALHOST), port),
+                CertificateRetrievalMode::GenerateSelfSigned {
+                    server_hostname: Ipv6Addr::LOCALHOST.to_string(),
+                },
+            ));
 
             commands.spawn((
                 Text::new("Server"),
@@ -102,20 +85,10 @@ fn setup(
             info!("connecting to {ip}:{port}");
 
             // Backend initialization
-            client
-                .open_connection(ClientConnectionConfiguration {
-                    addr_config: ClientAddrConfiguration::from_ips(
-                        ip,
-                        port,
-                        Ipv6Addr::UNSPECIFIED,
-                        0,
-                    ),
-                    cert_mode: CertificateVerificationMode::SkipVerification,
-                    defaultables: ClientConnectionConfigurationDefaultables {
-                        send_channels_cfg: channels.client_configs(),
-                    },
-                })
-                .unwrap();
+            commands.connect_replicon(
+                ClientAddrConfiguration::from_ips(ip, port, Ipv6Addr::UNSPECIFIED, 0),
+                CertificateVerificationMode::SkipVerification,
+            );
 
             commands.spawn((
                 Text(format!("Client")),
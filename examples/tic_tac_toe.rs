@@ -13,15 +13,16 @@ use bevy::{
 use bevy_quinnet::{
     client::{
         certificate::CertificateVerificationMode, connection::ClientAddrConfiguration,
-        ClientConnectionConfiguration, ClientConnectionConfigurationDefaultables, QuinnetClient,
-    },
-    server::{
-        certificate::CertificateRetrievalMode, EndpointAddrConfiguration, QuinnetServer,
-        ServerEndpointConfiguration, ServerEndpointConfigurationDefaultables,
+        QuinnetClient,
     },
+    server::{certificate::CertificateRetrievalMode, EndpointAddrConfiguration},
 };
 use bevy_replicon::prelude::*;
-use bevy_replicon_quinnet::{ChannelsConfigurationExt, RepliconQuinnetPlugins};
+use bevy_replicon_quinnet::{
+    client::RepliconQuinnetClientCommandsExt,
+    server::{RepliconQuinnetServerConfig, ShutdownServer},
+    RepliconQuinnetPlugins,
+};
 use clap::{Parser, ValueEnum};
 use serde::{Deserialize, Serialize};
 
@@ -89,13 +90,7 @@ const LINE_THICKNESS: f32 = 10.0;
 const BUTTON_SIZE: f32 = CELL_SIZE / 1.2;
 const BUTTON_MARGIN: f32 = (CELL_SIZE + LINE_THICKNESS - BUTTON_SIZE) / 2.0;
 
-fn read_cli(
-    mut commands: Commands,
-    cli: Res<Cli>,
-    channels: Res<RepliconChannels>,
-    mut server: ResMut<QuinnetServer>,
-    mut client: ResMut<QuinnetClient>,
-) -> Result<()> {
+fn read_cli(mut commands: Commands, cli: Res<Cli>) -> Result<()> {
     match *cli {
         Cli::Hotseat => {
             info!("starting hotseat");
@@ -108,20 +103,12 @@ fn read_cli(
             info!("starting server as {symbol} at port {port}");
 
             // Backend initialization
-            server
-                .start_endpoint(ServerEndpointConfiguration {
-                    addr_config: EndpointAddrConfiguration::from_ip(
-                        IpAddr::V6(Ipv6Addr::LOCALHOST),
-                        port,
-                    ),
-                    cert_mode: CertificateRetrievalMode::GenerateSelfSigned {
-                        server_hostname: Ipv6Addr::LOCALHOST.to_string(),
-                    },
-                    defaultables: ServerEndpointConfigurationDefaultables {
-                        send_channels_cfg: channels.server_configs(),
-                    },
-                })
-                .unwrap();
+            commands.insert_resource(RepliconQuinnetServerConfig::new(
+                EndpointAddrConfiguration::from_ip(IpAddr::V6(Ipv6Addr::LOCALHOST), port),
+                CertificateRetrievalMode::GenerateSelfSigned {
+                    server_hostname: Ipv6Addr::LOCALHOST.to_string(),
+                },
+            ));
 
             commands.spawn((LocalPlayer, symbol));
         }
@@ -129,20 +116,10 @@ fn read_cli(
             info!("connecting to {ip}:{port}");
 
             // Backend initialization
-            client
-                .open_connection(ClientConnectionConfiguration {
-                    addr_config: ClientAddrConfiguration::from_ips(
-                        ip,
-                        port,
-                        Ipv6Addr::UNSPECIFIED,
-                        0,
-                    ),
-                    cert_mode: CertificateVerificationMode::SkipVerification,
-                    defaultables: ClientConnectionConfigurationDefaultables {
-                        send_channels_cfg: channels.client_configs(),
-                    },
-                })
-                .unwrap();
+            commands.connect_replicon(
+                ClientAddrConfiguration::from_ips(ip, port, Ipv6Addr::UNSPECIFIED, 0),
+                CertificateVerificationMode::SkipVerification,
+            );
 
             commands.spawn((LocalPlayer, ClientPlayer));
         }
@@ -384,8 +361,8 @@ fn disconnect_by_server(mut commands: Commands) {
 }
 
 /// Closes all sockets.
-fn stop_networking(mut commands: Commands) {
-    commands.remove_resource::<QuinnetServer>();
+fn stop_networking(mut commands: Commands, mut shutdown: MessageWriter<ShutdownServer>) {
+    shutdown.write(ShutdownServer::default());
     commands.remove_resource::<QuinnetClient>();
 }
 
@@ -0,0 +1,63 @@
+//! Optional authentication handshake before replicon starts.
+//!
+//! When [`ServerAuth`](crate::server::ServerAuth) is inserted, new connections don't get a
+//! [`ConnectedClient`](bevy_replicon::prelude::ConnectedClient) entity right away. The server sends
+//! a random challenge of [`CHALLENGE_LEN`] bytes on the Quinnet channel reserved by
+//! [`ChannelMapping::auth_channel`](crate::channels::ChannelMapping::auth_channel), the client
+//! answers with the response produced by [`ClientAuth`](crate::client::ClientAuth) (a session
+//! ticket for example) and the server's validator decides whether the client is admitted.
+//! Refused connections are closed with [`DisconnectReason::Kicked`](crate::DisconnectReason::Kicked).
+//!
+//! The client stays in [`ClientState::Connecting`](bevy_replicon::prelude::ClientState::Connecting)
+//! until it's accepted, so no replicon message is exchanged before. Authentication must be enabled
+//! on both peers, a client not accepted within [`ClientAuth::timeout`](crate::client::ClientAuth::timeout)
+//! closes its connection with [`DisconnectReason::AuthTimedOut`](crate::DisconnectReason::AuthTimedOut).
+
+use bytes::Bytes;
+
+/// Size of the challenge sent by the server.
+pub const CHALLENGE_LEN: usize = 16;
+
+/// Message sent by the server on the auth channel.
+///
+/// The client answers a challenge with its raw response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AuthMessage {
+    Challenge(Bytes),
+    Accepted,
+}
+
+impl AuthMessage {
+    const CHALLENGE: u8 = 0;
+    const ACCEPTED: u8 = 1;
+
+    #[cfg(feature = "server")]
+    pub(crate) fn encode(&self) -> Bytes {
+        use bytes::{BufMut, BytesMut};
+
+        match self {
+            Self::Challenge(challenge) => {
+                let mut payload = BytesMut::with_capacity(size_of::<u8>() + challenge.len());
+                payload.put_u8(Self::CHALLENGE);
+                payload.put(challenge.clone());
+                payload.freeze()
+            }
+            Self::Accepted => Bytes::from_static(&[Self::ACCEPTED]),
+        }
+    }
+
+    /// Returns `None` if the payload is malformed.
+    #[cfg(feature = "client")]
+    pub(crate) fn decode(mut payload: Bytes) -> Option<Self> {
+        use bytes::Buf;
+
+        if payload.is_empty() {
+            return None;
+        }
+        match payload.get_u8() {
+            Self::CHALLENGE if payload.len() == CHALLENGE_LEN => Some(Self::Challenge(payload)),
+            Self::ACCEPTED if payload.is_empty() => Some(Self::Accepted),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Mapping between replicon channels and Quinnet channels.
+//!
+//! Quinnet identifies channels with a `u8`. When replicon registers more channels than that, the
+//! first [`DIRECT_CHANNELS`] replicon channels keep a dedicated Quinnet channel and the remaining
+//! ones are multiplexed over one shared Quinnet channel per [`Channel`] kind, with the replicon
+//! channel id prepended to each payload as a [`MULTIPLEX_HEADER_LEN`] bytes header.
+//!
+//! Multiplexing preserves the guarantees of each [`Channel`] kind, but ordered messages of different
+//! replicon channels sharing the same Quinnet channel are also ordered relative to each other.
+//!
+//! The last Quinnet channel is reserved for the authentication handshake, see [`crate::auth`].
+
+use std::{
+    any,
+    fmt::{self, Display, Formatter},
+    iter,
+};
+
+use bevy::{
+    app::App,
+    ecs::{event::Event, message::Message, resource::Resource},
+    log::warn,
+};
+use bevy_quinnet::shared::channels::{ChannelConfig, SendChannelsConfiguration};
+use bevy_replicon::{
+    prelude::{Channel, RepliconChannels},
+    shared::message::registry::RemoteMessageRegistry,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Maximum number of Quinnet channels.
+pub const MAX_QUINNET_CHANNELS: usize = u8::MAX as usize;
+
+/// Number of Quinnet channels reserved after the replicon channels, see [`ChannelMapping::auth_channel`].
+pub const RESERVED_CHANNELS: usize = 1;
+
+/// Number of replicon channels mapped to a dedicated Quinnet channel when multiplexing.
+pub const DIRECT_CHANNELS: usize = MAX_QUINNET_CHANNELS - RESERVED_CHANNELS - 3;
+
+/// Size of the replicon channel id prepended to multiplexed payloads.
+pub const MULTIPLEX_HEADER_LEN: usize = size_of::<u16>();
+
+/// Maximum number of replicon channels that can be carried over Quinnet.
+pub const MAX_REPLICON_CHANNELS: usize = DIRECT_CHANNELS + u16::MAX as usize + 1;
+
+/// Size of the channel id prepended by Quinnet to each datagram of unreliable channels.
+pub const QUINNET_DATAGRAM_HEADER_LEN: usize = size_of::<u8>();
+
+/// Maximum frame size of a reliable Quinnet channel, limited by its `u32` length prefix.
+pub const MAX_RELIABLE_FRAME_SIZE: usize = u32::MAX as usize;
+
+/// Error returned when replicon channels can't be converted into Quinnet channel configs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelConfigError {
+    /// Replicon registered more than [`MAX_REPLICON_CHANNELS`] channels.
+    TooManyChannels { count: usize },
+    /// The maximum reliable payload size is zero or exceeds [`MAX_RELIABLE_FRAME_SIZE`].
+    PayloadSizeOutOfRange { size: usize },
+    /// The maximum reliable payload size leaves no room for the header of multiplexed channels.
+    IncompatibleFrameSize { size: usize },
+}
+
+impl Display for ChannelConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyChannels { count } => write!(
+                f,
+                "{count} channels exceed the maximum of {MAX_REPLICON_CHANNELS}"
+            ),
+            Self::PayloadSizeOutOfRange { size } => write!(
+                f,
+                "payload size {size} is out of range 1..={MAX_RELIABLE_FRAME_SIZE}"
+            ),
+            Self::IncompatibleFrameSize { size } => write!(
+                f,
+                "payload size {size} with the {MULTIPLEX_HEADER_LEN} bytes multiplexing header exceeds {MAX_RELIABLE_FRAME_SIZE}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChannelConfigError {}
+
+/// Quinnet settings overriding the defaults of a replicon channel, see [`QuinnetChannelOverrides`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelOverride {
+    /// Maximum frame size of a reliable channel, ignored for unreliable channels.
+    pub max_frame_size: Option<usize>,
+}
+
+impl ChannelOverride {
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// Returns `other` with unset fields taken from `self`.
+    fn merge(self, other: Self) -> Self {
+        Self {
+            max_frame_size: other.max_frame_size.or(self.max_frame_size),
+        }
+    }
+}
+
+/// Per-channel Quinnet settings, keyed by replicon channel id or by registered message or event type.
+///
+/// Should be inserted after all replicon messages and events have been registered but before the app
+/// is finished, since channel mappings are built when plugins are finished.
+///
+/// ```ignore
+/// app.add_server_message::<Chat>(Channel::Ordered)
+///     .add_server_event::<Snapshot>(Channel::Ordered)
+///     .insert_resource(
+///         QuinnetChannelOverrides::default()
+///             .server_message::<Chat>(ChannelOverride::default().with_max_frame_size(512))
+///             .server_event::<Snapshot>(ChannelOverride::default().with_max_frame_size(64 * 1024)),
+///     );
+/// ```
+#[derive(Resource, Default)]
+pub struct QuinnetChannelOverrides {
+    server: Vec<(ChannelSelector, ChannelOverride)>,
+    client: Vec<(ChannelSelector, ChannelOverride)>,
+}
+
+impl QuinnetChannelOverrides {
+    /// Overrides the server channel `channel_id`.
+    pub fn server_channel(mut self, channel_id: usize, channel_override: ChannelOverride) -> Self {
+        self.server
+            .push((ChannelSelector::Id(channel_id), channel_override));
+        self
+    }
+
+    /// Overrides the server channel of the message `M`.
+    pub fn server_message<M: Message>(mut self, channel_override: ChannelOverride) -> Self {
+        self.server.push((
+            ChannelSelector::by_type::<M>(|registry| registry.server_message_channel::<M>()),
+            channel_override,
+        ));
+        self
+    }
+
+    /// Overrides the server channel of the event `E`.
+    pub fn server_event<E: Event>(mut self, channel_override: ChannelOverride) -> Self {
+        self.server.push((
+            ChannelSelector::by_type::<E>(|registry| registry.server_event_channel::<E>()),
+            channel_override,
+        ));
+        self
+    }
+
+    /// Overrides the client channel `channel_id`.
+    pub fn client_channel(mut self, channel_id: usize, channel_override: ChannelOverride) -> Self {
+        self.client
+            .push((ChannelSelector::Id(channel_id), channel_override));
+        self
+    }
+
+    /// Overrides the client channel of the message `M`.
+    pub fn client_message<M: Message>(mut self, channel_override: ChannelOverride) -> Self {
+        self.client.push((
+            ChannelSelector::by_type::<M>(|registry| registry.client_message_channel::<M>()),
+            channel_override,
+        ));
+        self
+    }
+
+    /// Overrides the client channel of the event `E`.
+    pub fn client_event<E: Event>(mut self, channel_override: ChannelOverride) -> Self {
+        self.client.push((
+            ChannelSelector::by_type::<E>(|registry| registry.client_event_channel::<E>()),
+            channel_override,
+        ));
+        self
+    }
+}
+
+type ChannelResolver = Box<dyn Fn(&RemoteMessageRegistry) -> Option<usize> + Send + Sync>;
+
+enum ChannelSelector {
+    Id(usize),
+    Type {
+        type_name: &'static str,
+        resolve: ChannelResolver,
+    },
+}
+
+impl ChannelSelector {
+    fn by_type<T>(
+        resolve: impl Fn(&RemoteMessageRegistry) -> Option<usize> + Send + Sync + 'static,
+    ) -> Self {
+        Self::Type {
+            type_name: any::type_name::<T>(),
+            resolve: Box::new(resolve),
+        }
+    }
+
+    fn resolve(&self, registry: &RemoteMessageRegistry) -> Option<usize> {
+        match self {
+            Self::Id(channel_id) => Some(*channel_id),
+            Self::Type { resolve, .. } => resolve(registry),
+        }
+    }
+}
+
+impl Display for ChannelSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Id(channel_id) => write!(f, "channel {channel_id}"),
+            Self::Type { type_name, .. } => write!(f, "`{type_name}`"),
+        }
+    }
+}
+
+/// Maps the replicon channels of one side (server or client) to Quinnet channels.
+#[derive(Debug, Clone)]
+pub struct ChannelMapping {
+    channels: Vec<Channel>,
+    overrides: Vec<ChannelOverride>,
+}
+
+impl ChannelMapping {
+    pub fn new(channels: &[Channel]) -> Self {
+        Self {
+            channels: channels.to_vec(),
+            overrides: vec![Default::default(); channels.len()],
+        }
+    }
+
+    /// Returns `true` if some replicon channels share a Quinnet channel.
+    pub fn is_multiplexed(&self) -> bool {
+        self.channels.len() > MAX_QUINNET_CHANNELS - RESERVED_CHANNELS
+    }
+
+    /// Returns the maximum size of a replicon message that fits in a datagram of `datagram_size` bytes.
+    ///
+    /// Accounts for [`QUINNET_DATAGRAM_HEADER_LEN`] and, if the mapping is multiplexed, for
+    /// [`MULTIPLEX_HEADER_LEN`].
+    pub fn max_message_size(&self, datagram_size: usize) -> usize {
+        datagram_size.saturating_sub(QUINNET_DATAGRAM_HEADER_LEN + self.message_header_len())
+    }
+
+    /// Returns the size of the header prepended to replicon messages by [`Self::encode`].
+    pub fn message_header_len(&self) -> usize {
+        if self.is_multiplexed() {
+            MULTIPLEX_HEADER_LEN
+        } else {
+            0
+        }
+    }
+
+    /// Returns the Quinnet channel reserved for the authentication handshake.
+    ///
+    /// It's an ordered reliable channel following the channels of replicon.
+    pub fn auth_channel(&self) -> u8 {
+        if self.is_multiplexed() {
+            (DIRECT_CHANNELS + 3) as u8
+        } else {
+            self.channels.len() as u8
+        }
+    }
+
+    /// Returns the kind of a replicon channel.
+    pub fn channel(&self, channel_id: usize) -> Option<Channel> {
+        self.channels.get(channel_id).copied()
+    }
+
+    /// Returns the kind of a Quinnet channel.
+    pub fn quinnet_channel(&self, quinnet_channel: u8) -> Option<Channel> {
+        let quinnet_channel = quinnet_channel as usize;
+        if !self.is_multiplexed() || quinnet_channel < DIRECT_CHANNELS {
+            return self.channel(quinnet_channel);
+        }
+
+        match quinnet_channel - DIRECT_CHANNELS {
+            0 => Some(Channel::Unreliable),
+            1 => Some(Channel::Unordered),
+            2 => Some(Channel::Ordered),
+            _ => None,
+        }
+    }
+
+    /// Converts replicon channels into Quinnet channel configs.
+    ///
+    /// `max_frame_size` is used for reliable channels without [`ChannelOverride::max_frame_size`].
+    pub fn quinnet_configs(
+        &self,
+        max_frame_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError> {
+        if self.channels.len() > MAX_REPLICON_CHANNELS {
+            return Err(ChannelConfigError::TooManyChannels {
+                count: self.channels.len(),
+            });
+        }
+        let frame_sizes: Vec<_> = self
+            .overrides
+            .iter()
+            .map(|channel_override| channel_override.max_frame_size.unwrap_or(max_frame_size))
+            .collect();
+        for &size in iter::once(&max_frame_size).chain(&frame_sizes) {
+            if size == 0 || size > MAX_RELIABLE_FRAME_SIZE {
+                return Err(ChannelConfigError::PayloadSizeOutOfRange { size });
+            }
+        }
+
+        let mut quinnet_channels = SendChannelsConfiguration::new();
+        if !self.is_multiplexed() {
+            for (&channel, &size) in self.channels.iter().zip(&frame_sizes) {
+                quinnet_channels.add(channel_config(channel, size));
+            }
+            quinnet_channels.add(channel_config(Channel::Ordered, max_frame_size));
+            return Ok(quinnet_channels);
+        }
+
+        for (&channel, &size) in self.channels[..DIRECT_CHANNELS].iter().zip(&frame_sizes) {
+            quinnet_channels.add(channel_config(channel, size));
+        }
+        for shared_channel in [Channel::Unreliable, Channel::Unordered, Channel::Ordered] {
+            // Shared channels must accept the largest frame of their replicon channels.
+            let size = self.channels[DIRECT_CHANNELS..]
+                .iter()
+                .zip(&frame_sizes[DIRECT_CHANNELS..])
+                .filter(|(channel, _)| **channel == shared_channel)
+                .map(|(_, &size)| size)
+                .max()
+                .unwrap_or(max_frame_size);
+            // Reserve space for the header to keep the same payload limit.
+            if size > MAX_RELIABLE_FRAME_SIZE - MULTIPLEX_HEADER_LEN {
+                return Err(ChannelConfigError::IncompatibleFrameSize { size });
+            }
+            quinnet_channels.add(channel_config(shared_channel, size + MULTIPLEX_HEADER_LEN));
+        }
+        quinnet_channels.add(channel_config(Channel::Ordered, max_frame_size));
+        Ok(quinnet_channels)
+    }
+
+    /// Returns the Quinnet channel and payload to send a replicon message on `channel_id`.
+    pub fn encode(&self, channel_id: usize, message: Bytes) -> (u8, Bytes) {
+        if !self.is_multiplexed() || channel_id < DIRECT_CHANNELS {
+            return (channel_id as u8, message);
+        }
+
+        let shared_id = match self.channels[channel_id] {
+            Channel::Unreliable => DIRECT_CHANNELS,
+            Channel::Unordered => DIRECT_CHANNELS + 1,
+            Channel::Ordered => DIRECT_CHANNELS + 2,
+        };
+        let mut payload = BytesMut::with_capacity(MULTIPLEX_HEADER_LEN + message.len());
+        payload.put_u16_le((channel_id - DIRECT_CHANNELS) as u16);
+        payload.put(message);
+        (shared_id as u8, payload.freeze())
+    }
+
+    /// Returns the replicon channel and message of a payload received on a Quinnet channel.
+    ///
+    /// Returns `None` if the payload is malformed or wasn't sent on a replicon channel.
+    pub fn decode(&self, quinnet_channel: u8, payload: Bytes) -> Option<(usize, Bytes)> {
+        self.quinnet_channel(quinnet_channel)?;
+        let quinnet_channel = quinnet_channel as usize;
+        if !self.is_multiplexed() || quinnet_channel < DIRECT_CHANNELS {
+            return Some((quinnet_channel, payload));
+        }
+
+        let header = payload.get(..MULTIPLEX_HEADER_LEN)?;
+        let channel_id = DIRECT_CHANNELS + u16::from_le_bytes([header[0], header[1]]) as usize;
+        if channel_id >= self.channels.len() {
+            return None;
+        }
+        Some((channel_id, payload.slice(MULTIPLEX_HEADER_LEN..)))
+    }
+
+    fn apply_overrides(
+        &mut self,
+        overrides: &[(ChannelSelector, ChannelOverride)],
+        registry: &RemoteMessageRegistry,
+        side: &str,
+    ) {
+        for (selector, channel_override) in overrides {
+            match selector.resolve(registry) {
+                Some(channel_id) if channel_id < self.overrides.len() => {
+                    let current = &mut self.overrides[channel_id];
+                    *current = current.merge(*channel_override);
+                }
+                _ => warn!("ignoring override of unregistered {side} {selector}"),
+            }
+        }
+    }
+}
+
+fn channel_config(channel: Channel, max_frame_size: usize) -> ChannelConfig {
+    match channel {
+        Channel::Unreliable => ChannelConfig::Unreliable,
+        Channel::Unordered => ChannelConfig::UnorderedReliable { max_frame_size },
+        Channel::Ordered => ChannelConfig::OrderedReliable { max_frame_size },
+    }
+}
+
+/// Channel mappings of both sides, built from [`RepliconChannels`] and [`QuinnetChannelOverrides`]
+/// once all channels are registered.
+#[derive(Resource, Debug, Clone)]
+pub struct ChannelMappings {
+    pub(crate) server: ChannelMapping,
+    pub(crate) client: ChannelMapping,
+}
+
+impl ChannelMappings {
+    pub fn server(&self) -> &ChannelMapping {
+        &self.server
+    }
+
+    pub fn client(&self) -> &ChannelMapping {
+        &self.client
+    }
+
+    /// Inserts the mappings if they were not already inserted by the other plugin.
+    pub(crate) fn init(app: &mut App) {
+        if app.world().contains_resource::<Self>() {
+            return;
+        }
+        let world = app.world();
+        let channels = world.resource::<RepliconChannels>();
+        let mut mappings = Self {
+            server: ChannelMapping::new(channels.server_channels()),
+            client: ChannelMapping::new(channels.client_channels()),
+        };
+        if let Some(overrides) = world.get_resource::<QuinnetChannelOverrides>() {
+            let registry = world.resource::<RemoteMessageRegistry>();
+            mappings
+                .server
+                .apply_overrides(&overrides.server, registry, "server");
+            mappings
+                .client
+                .apply_overrides(&overrides.client, registry, "client");
+        }
+        app.insert_resource(mappings);
+    }
+}
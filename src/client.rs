@@ -1,26 +1,106 @@
+use std::{iter, mem, time::Duration};
+
 use bevy::{
     app::{App, Plugin, PostUpdate, PreUpdate},
-    ecs::schedule::IntoScheduleConfigs,
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    ecs::{
+        change_detection::DetectChangesMut,
+        message::{Message, MessageReader, MessageWriter},
+        resource::Resource,
+        schedule::{common_conditions::resource_exists, IntoScheduleConfigs, SystemCondition},
+        system::Commands,
+        world::{Mut, World},
+    },
+    log::{debug, error, warn},
     prelude::{Local, Res, ResMut},
-    state::state::NextState,
+    state::state::{NextState, State},
     time::Time,
 };
 use bevy_quinnet::{
-    client::{QuinnetClient, QuinnetClientPlugin},
-    shared::QuinnetSyncPreUpdate,
+    client::{
+        certificate::CertificateVerificationMode,
+        connection::{
+            ClientAddrConfiguration, ClientSideConnection, ConnectionFailedEvent,
+            ConnectionLocalId, ConnectionLostEvent,
+        },
+        ClientConnectionConfiguration, ClientConnectionConfigurationDefaultables, QuinnetClient,
+        QuinnetClientPlugin,
+    },
+    shared::{channels::DEFAULT_MAX_RELIABLE_FRAME_LEN, QuinnetSyncPreUpdate},
 };
 use bevy_replicon::{
     client::ClientSystems,
-    prelude::{ClientMessages, ClientState, ClientStats},
+    prelude::{Channel, ClientMessages, ClientState, ClientStats, RepliconChannels},
+};
+use bytes::Bytes;
+
+use crate::{
+    auth::AuthMessage,
+    channels::{ChannelMappings, QUINNET_DATAGRAM_HEADER_LEN},
+    fragmentation::{FragmentBuffer, UnreliableFragmentation},
+    random_bytes,
+    stats::{PathStatsWindow, QuinnetConnectionDiagnostics},
+    DisconnectReason, SendErrorKind, SendErrorPolicy, TransportSendError, BYTES_PER_SEC_PERIOD,
+    DEFAULT_INITIAL_MAX_SIZE,
 };
 
-use crate::BYTES_PER_SEC_PERIOD;
+#[derive(Default)]
+pub struct RepliconQuinnetClientPlugin {
+    /// Inserted as a resource to reconnect automatically, disabled by default.
+    pub reconnect_policy: Option<ReconnectPolicy>,
+}
 
-pub struct RepliconQuinnetClientPlugin;
+impl RepliconQuinnetClientPlugin {
+    /// Round-trip time of the connection, in seconds.
+    pub const RTT: DiagnosticPath = DiagnosticPath::const_new("replicon_quinnet/client/rtt");
+    /// Percentage of packets lost over the last [`PathStatsWindow`].
+    pub const PACKET_LOSS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/client/packet_loss");
+    /// RTT jitter over the last [`PathStatsWindow`], in seconds.
+    pub const JITTER: DiagnosticPath = DiagnosticPath::const_new("replicon_quinnet/client/jitter");
+    /// Bytes sent per second.
+    pub const SENT_BPS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/client/sent_bps");
+    /// Bytes received per second.
+    pub const RECEIVED_BPS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/client/received_bps");
+    /// Number of unreliable messages rejected during the last update because they didn't fit in
+    /// [`MaxDatagramSize`], even after fragmentation if enabled.
+    pub const OVERSIZE_MESSAGES: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/client/oversize_messages");
+
+    /// Number of messages handed to Quinnet on the client channel `channel_id` during the last update.
+    ///
+    /// Quinnet doesn't expose the depth of its send queues, so this counts what replicon queued on
+    /// the channel since the previous update. Registered for every client channel.
+    pub fn channel_sent_messages(channel_id: usize) -> DiagnosticPath {
+        DiagnosticPath::new(format!(
+            "replicon_quinnet/client/channel_{channel_id}/sent_messages"
+        ))
+    }
+}
 
 impl Plugin for RepliconQuinnetClientPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(QuinnetClientPlugin::default())
+            .add_message::<ClientConnectFailed>()
+            .add_message::<TransportSendError>()
+            .init_resource::<SendErrorPolicy>()
+            .init_resource::<PathStatsWindow>()
+            .init_resource::<MaxDatagramSize>()
+            .init_resource::<FragmentBuffer>()
+            .init_resource::<ActiveConnection>()
+            .init_resource::<AuthState>()
+            .add_message::<HandoffCompleted>()
+            .add_message::<HandoffFailed>()
+            .add_message::<ReconnectProgress>()
+            .add_message::<ServerDisconnected>()
+            .register_diagnostic(Diagnostic::new(Self::RTT).with_suffix(" s"))
+            .register_diagnostic(Diagnostic::new(Self::PACKET_LOSS).with_suffix(" %"))
+            .register_diagnostic(Diagnostic::new(Self::JITTER).with_suffix(" s"))
+            .register_diagnostic(Diagnostic::new(Self::SENT_BPS).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::RECEIVED_BPS).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::OVERSIZE_MESSAGES))
             .configure_sets(
                 PreUpdate,
                 ClientSystems::ReceivePackets.after(QuinnetSyncPreUpdate),
@@ -28,11 +108,16 @@ impl Plugin for RepliconQuinnetClientPlugin {
             .add_systems(
                 PreUpdate,
                 (
-                    set_connected.run_if(bevy_quinnet::client::client_just_connected),
-                    set_connecting.run_if(bevy_quinnet::client::client_connecting),
-                    set_disconnected.run_if(bevy_quinnet::client::client_just_disconnected),
-                    (receive_packets, update_statistics)
-                        .run_if(bevy_quinnet::client::client_connected),
+                    (process_handoff, reconnect, process_auth, update_state)
+                        .chain()
+                        .run_if(resource_exists::<QuinnetClient>),
+                    (
+                        receive_packets,
+                        update_statistics,
+                        measure_diagnostics.after(update_statistics),
+                    )
+                        .after(update_state)
+                        .run_if(active_connected.and(authenticated)),
                 )
                     .in_set(ClientSystems::ReceivePackets),
             )
@@ -40,39 +125,775 @@ impl Plugin for RepliconQuinnetClientPlugin {
                 PostUpdate,
                 send_packets
                     .in_set(ClientSystems::SendPackets)
-                    .run_if(bevy_quinnet::client::client_connected),
+                    .run_if(active_connected.and(authenticated)),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        ChannelMappings::init(app);
+        if let Some(reconnect_policy) = self.reconnect_policy {
+            app.insert_resource(reconnect_policy);
+        }
+
+        let channels_count = app
+            .world()
+            .resource::<RepliconChannels>()
+            .client_channels()
+            .len();
+        let channel_paths: Vec<_> = (0..channels_count)
+            .map(Self::channel_sent_messages)
+            .collect();
+        for path in &channel_paths {
+            app.register_diagnostic(Diagnostic::new(path.clone()));
+        }
+        app.insert_resource(ChannelDiagnosticPaths(channel_paths));
+    }
+}
+
+/// Diagnostic paths of [`RepliconQuinnetClientPlugin::channel_sent_messages`], indexed by channel id.
+#[derive(Resource)]
+struct ChannelDiagnosticPaths(Vec<DiagnosticPath>);
+
+/// Quinnet connection driving replicon's [`ClientMessages`] and [`ClientState`].
+///
+/// Other connections of the [`QuinnetClient`] are left untouched, so they can be used for
+/// non-replicated traffic (a lobby for example). When no connection is selected, the default
+/// connection of the [`QuinnetClient`] is used.
+///
+/// Switching away from a connected connection goes through [`ClientState::Disconnected`] for one
+/// update, so replicon resets its state for the previous server. Handoffs started with
+/// [`RepliconQuinnetClientCommandsExt::handoff_replicon`] go through [`ClientState::Connecting`]
+/// instead.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct ActiveConnection {
+    connection_id: Option<ConnectionLocalId>,
+    handoff: bool,
+}
+
+impl ActiveConnection {
+    pub fn new(connection_id: ConnectionLocalId) -> Self {
+        Self {
+            connection_id: Some(connection_id),
+            handoff: false,
+        }
+    }
+
+    /// Returns the selected connection, `None` if the default connection is used.
+    pub fn get(&self) -> Option<ConnectionLocalId> {
+        self.connection_id
+    }
+
+    /// Selects the connection used by replicon, `None` to use the default connection.
+    pub fn set(&mut self, connection_id: Option<ConnectionLocalId>) {
+        self.connection_id = connection_id;
+        self.handoff = false;
+    }
+
+    /// Returns the id of the connection used by replicon.
+    pub fn resolve(&self, quinnet_client: &QuinnetClient) -> Option<ConnectionLocalId> {
+        self.connection_id
+            .or_else(|| quinnet_client.get_default_connection())
+    }
+
+    fn connection<'a>(
+        &self,
+        quinnet_client: &'a QuinnetClient,
+    ) -> Option<&'a ClientSideConnection> {
+        quinnet_client.get_connection_by_id(self.resolve(quinnet_client)?)
+    }
+
+    fn connection_mut<'a>(
+        &self,
+        quinnet_client: &'a mut QuinnetClient,
+    ) -> Option<&'a mut ClientSideConnection> {
+        let connection_id = self.resolve(quinnet_client)?;
+        quinnet_client.get_connection_mut_by_id(connection_id)
+    }
+}
+
+pub trait RepliconQuinnetClientCommandsExt {
+    /// Opens a connection on the [`QuinnetClient`] with send channels derived from
+    /// [`RepliconChannels`] and makes it the
+    /// [`ActiveConnection`].
+    ///
+    /// Should be used after all replicon messages and events have been registered.
+    /// If the connection can't be opened, a [`ClientConnectFailed`] message is written.
+    fn connect_replicon(
+        &mut self,
+        addr_config: ClientAddrConfiguration,
+        cert_mode: CertificateVerificationMode,
+    );
+
+    /// Moves replicon to another server without going through [`ClientState::Disconnected`].
+    ///
+    /// Opens a connection like [`Self::connect_replicon`] while the [`ActiveConnection`] keeps
+    /// feeding replicon. Once the new connection is established, it becomes the active one, the
+    /// previous connection is closed and a [`HandoffCompleted`] message is written. Replicon goes
+    /// through [`ClientState::Connecting`] for one update to reset its state for the previous server.
+    ///
+    /// If the new connection can't be established, a [`HandoffFailed`] message is written and the
+    /// active connection is kept. Starting a handoff while another one is pending cancels the pending one.
+    fn handoff_replicon(
+        &mut self,
+        addr_config: ClientAddrConfiguration,
+        cert_mode: CertificateVerificationMode,
+    );
+}
+
+impl RepliconQuinnetClientCommandsExt for Commands<'_, '_> {
+    fn connect_replicon(
+        &mut self,
+        addr_config: ClientAddrConfiguration,
+        cert_mode: CertificateVerificationMode,
+    ) {
+        self.queue(move |world: &mut World| connect_replicon(world, addr_config, cert_mode));
+    }
+
+    fn handoff_replicon(
+        &mut self,
+        addr_config: ClientAddrConfiguration,
+        cert_mode: CertificateVerificationMode,
+    ) {
+        self.queue(move |world: &mut World| handoff_replicon(world, addr_config, cert_mode));
+    }
+}
+
+/// Written when a connection requested with [`RepliconQuinnetClientCommandsExt::connect_replicon`] could not be opened.
+#[derive(Message, Debug, Clone)]
+pub struct ClientConnectFailed {
+    pub error: String,
+}
+
+/// Written when a handoff started with [`RepliconQuinnetClientCommandsExt::handoff_replicon`] completes.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct HandoffCompleted {
+    /// Closed connection, `None` if there was no active connection.
+    pub previous: Option<ConnectionLocalId>,
+    /// New [`ActiveConnection`].
+    pub connection: ConnectionLocalId,
+}
+
+/// Written when a handoff started with [`RepliconQuinnetClientCommandsExt::handoff_replicon`] fails.
+#[derive(Message, Debug, Clone)]
+pub struct HandoffFailed {
+    pub error: String,
+}
+
+/// Connection of an ongoing handoff, see [`RepliconQuinnetClientCommandsExt::handoff_replicon`].
+#[derive(Resource, Debug, Clone)]
+pub struct PendingHandoff {
+    connection_id: ConnectionLocalId,
+    config: LastConnection,
+}
+
+impl PendingHandoff {
+    pub fn connection_id(&self) -> ConnectionLocalId {
+        self.connection_id
+    }
+}
+
+/// Configuration of the last connection opened with [`RepliconQuinnetClientCommandsExt`], used to reconnect.
+#[derive(Resource, Debug, Clone)]
+struct LastConnection {
+    addr_config: ClientAddrConfiguration,
+    cert_mode: CertificateVerificationMode,
+}
+
+fn connect_replicon(
+    world: &mut World,
+    addr_config: ClientAddrConfiguration,
+    cert_mode: CertificateVerificationMode,
+) {
+    let config = LastConnection {
+        addr_config,
+        cert_mode,
+    };
+    match open_connection(world, &config) {
+        Ok(connection_id) => {
+            world
+                .resource_mut::<ActiveConnection>()
+                .set(Some(connection_id));
+            world.insert_resource(config);
+        }
+        Err(error) => {
+            error!("unable to open the client connection: {error}");
+            world.write_message(ClientConnectFailed { error });
+        }
+    }
+}
+
+fn handoff_replicon(
+    world: &mut World,
+    addr_config: ClientAddrConfiguration,
+    cert_mode: CertificateVerificationMode,
+) {
+    if let Some(pending) = world.remove_resource::<PendingHandoff>() {
+        debug!("cancelling handoff to {:?}", pending.connection_id);
+        let mut quinnet_client = world.resource_mut::<QuinnetClient>();
+        quinnet_client.close_connection(pending.connection_id).ok();
+    }
+
+    let config = LastConnection {
+        addr_config,
+        cert_mode,
+    };
+    match open_connection(world, &config) {
+        Ok(connection_id) => world.insert_resource(PendingHandoff {
+            connection_id,
+            config,
+        }),
+        Err(error) => {
+            error!("unable to open the handoff connection: {error}");
+            world.write_message(HandoffFailed { error });
+        }
+    }
+}
+
+fn open_connection(
+    world: &mut World,
+    config: &LastConnection,
+) -> Result<ConnectionLocalId, String> {
+    world.resource_scope(|world, mut quinnet_client: Mut<QuinnetClient>| {
+        open_quinnet_connection(
+            &mut quinnet_client,
+            world.resource::<ChannelMappings>(),
+            config,
+        )
+    })
+}
+
+/// Opens a connection with send channels derived from [`ChannelMappings`].
+fn open_quinnet_connection(
+    quinnet_client: &mut QuinnetClient,
+    mappings: &ChannelMappings,
+    config: &LastConnection,
+) -> Result<ConnectionLocalId, String> {
+    let send_channels_cfg = mappings
+        .client
+        .quinnet_configs(DEFAULT_MAX_RELIABLE_FRAME_LEN)
+        .map_err(|err| err.to_string())?;
+
+    quinnet_client
+        .open_connection(ClientConnectionConfiguration {
+            addr_config: config.addr_config.clone(),
+            cert_mode: config.cert_mode.clone(),
+            defaultables: ClientConnectionConfigurationDefaultables { send_channels_cfg },
+        })
+        .map_err(|err| err.to_string())
+}
+
+/// Swaps the [`ActiveConnection`] once the [`PendingHandoff`] connection is established.
+#[allow(clippy::too_many_arguments)]
+fn process_handoff(
+    mut commands: Commands,
+    mut lost_events: MessageReader<ConnectionLostEvent>,
+    mut failed_events: MessageReader<ConnectionFailedEvent>,
+    mut completed_handoffs: MessageWriter<HandoffCompleted>,
+    mut failed_handoffs: MessageWriter<HandoffFailed>,
+    mut quinnet_client: ResMut<QuinnetClient>,
+    mut active: ResMut<ActiveConnection>,
+    pending: Option<Res<PendingHandoff>>,
+) {
+    let Some(pending) = pending else {
+        lost_events.clear();
+        failed_events.clear();
+        return;
+    };
+
+    let failed = lost_events
+        .read()
+        .map(|event| event.id)
+        .chain(failed_events.read().map(|event| event.id))
+        .any(|event_id| event_id == pending.connection_id);
+    if failed {
+        warn!("handoff to {:?} failed", pending.connection_id);
+        commands.remove_resource::<PendingHandoff>();
+        failed_handoffs.write(HandoffFailed {
+            error: "unable to connect to the target server".to_string(),
+        });
+        return;
+    }
+
+    let connected = quinnet_client
+        .get_connection_by_id(pending.connection_id)
+        .is_some_and(|connection| connection.is_connected());
+    if !connected {
+        return;
+    }
+
+    let previous = active.resolve(&quinnet_client);
+    debug!(
+        "handing off replicon from {previous:?} to {:?}",
+        pending.connection_id
+    );
+    active.connection_id = Some(pending.connection_id);
+    active.handoff = true;
+    if let Some(previous) = previous.filter(|&previous| previous != pending.connection_id) {
+        quinnet_client.close_connection(previous).ok();
+    }
+    commands.remove_resource::<PendingHandoff>();
+    commands.insert_resource(pending.config.clone());
+    completed_handoffs.write(HandoffCompleted {
+        previous,
+        connection: pending.connection_id,
+    });
+}
+
+/// Enables automatic reconnection when the [`ActiveConnection`] is lost.
+///
+/// Only connections opened with [`RepliconQuinnetClientCommandsExt`] can be reopened. Closing the
+/// connection locally doesn't trigger a reconnection. While reconnecting, the [`Reconnecting`] resource
+/// is present and [`ReconnectProgress`] messages are written for each step.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    /// Maximum number of attempts before giving up, `None` for unlimited attempts.
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+    /// Upper bound of the delay between attempts.
+    pub max_delay: Duration,
+    /// Factor applied to the delay after each attempt, expected to be at least `1.0`.
+    ///
+    /// Negative delays resulting from other values are treated as zero.
+    pub multiplier: f32,
+    /// Fraction of the delay randomly added or removed, from `0.0` to `1.0`.
+    pub jitter: f32,
+}
+
+impl ReconnectPolicy {
+    /// Returns the delay before `attempt`, starting from 1, without jitter.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial_delay.as_secs_f32() * self.multiplier.powi(exponent);
+        Duration::try_from_secs_f32(secs.clamp(0.0, self.max_delay.as_secs_f32()))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * random_unit() - 1.0);
+        self.base_delay(attempt).mul_f32(1.0 + jitter)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(5),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// Returns a random value in `0.0..1.0`.
+fn random_unit() -> f32 {
+    let bits = u32::from_le_bytes(random_bytes());
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Present while reconnecting according to the [`ReconnectPolicy`].
+///
+/// Distinguishes a connection being restored from a terminal [`ClientState::Disconnected`].
+/// Removing this resource cancels the reconnection.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Reconnecting {
+    attempt: u32,
+    delay: Duration,
+    connection_id: Option<ConnectionLocalId>,
+}
+
+impl Reconnecting {
+    /// Returns the current attempt, starting from 1.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Returns the remaining delay before the attempt, zero once started.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+}
+
+/// Written by [`RepliconQuinnetClientPlugin`] while reconnecting, see [`ReconnectPolicy`].
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectProgress {
+    /// The connection was lost and an attempt is scheduled after `delay`.
+    Scheduled { attempt: u32, delay: Duration },
+    /// The connection is being reopened.
+    Attempting { attempt: u32 },
+    /// The connection was restored.
+    Succeeded { attempts: u32 },
+    /// All attempts failed, the client stays disconnected.
+    GaveUp { attempts: u32 },
+}
+
+/// Reopens the [`LastConnection`] when the [`ActiveConnection`] is lost.
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    mut commands: Commands,
+    mut lost_events: MessageReader<ConnectionLostEvent>,
+    mut failed_events: MessageReader<ConnectionFailedEvent>,
+    mut progress: MessageWriter<ReconnectProgress>,
+    mut quinnet_client: ResMut<QuinnetClient>,
+    mut active: ResMut<ActiveConnection>,
+    reconnecting: Option<ResMut<Reconnecting>>,
+    policy: Option<Res<ReconnectPolicy>>,
+    last_connection: Option<Res<LastConnection>>,
+    mappings: Res<ChannelMappings>,
+    time: Res<Time>,
+) {
+    let connection_id = active.resolve(&quinnet_client);
+    let lost = lost_events
+        .read()
+        .map(|event| event.id)
+        .chain(failed_events.read().map(|event| event.id))
+        .any(|event_id| Some(event_id) == connection_id);
+    let (Some(policy), Some(last_connection)) = (policy, last_connection) else {
+        return;
+    };
+
+    let Some(mut reconnecting) = reconnecting else {
+        if lost {
+            let delay = policy.delay(1);
+            warn!("connection lost, reconnecting in {delay:?}");
+            commands.insert_resource(Reconnecting {
+                attempt: 1,
+                delay,
+                connection_id: None,
+            });
+            progress.write(ReconnectProgress::Scheduled { attempt: 1, delay });
+        }
+        return;
+    };
+
+    if let Some(attempt_id) = reconnecting.connection_id {
+        if lost {
+            quinnet_client.close_connection(attempt_id).ok();
+            if policy
+                .max_attempts
+                .is_some_and(|max_attempts| reconnecting.attempt >= max_attempts)
+            {
+                error!(
+                    "giving up after {} reconnect attempts",
+                    reconnecting.attempt
+                );
+                commands.remove_resource::<Reconnecting>();
+                progress.write(ReconnectProgress::GaveUp {
+                    attempts: reconnecting.attempt,
+                });
+            } else {
+                reconnecting.attempt += 1;
+                reconnecting.delay = policy.delay(reconnecting.attempt);
+                reconnecting.connection_id = None;
+                debug!(
+                    "reconnect attempt failed, next attempt in {:?}",
+                    reconnecting.delay
+                );
+                progress.write(ReconnectProgress::Scheduled {
+                    attempt: reconnecting.attempt,
+                    delay: reconnecting.delay,
+                });
+            }
+        } else if active
+            .connection(&quinnet_client)
+            .is_some_and(|connection| connection.is_connected())
+        {
+            debug!("reconnected after {} attempts", reconnecting.attempt);
+            commands.remove_resource::<Reconnecting>();
+            progress.write(ReconnectProgress::Succeeded {
+                attempts: reconnecting.attempt,
+            });
+        }
+        return;
+    }
+
+    reconnecting.delay = reconnecting.delay.saturating_sub(time.delta());
+    if !reconnecting.delay.is_zero() {
+        return;
+    }
+
+    if let Some(connection_id) = connection_id {
+        quinnet_client.close_connection(connection_id).ok();
+    }
+    progress.write(ReconnectProgress::Attempting {
+        attempt: reconnecting.attempt,
+    });
+    match open_quinnet_connection(&mut quinnet_client, &mappings, &last_connection) {
+        Ok(connection_id) => {
+            active.set(Some(connection_id));
+            reconnecting.connection_id = Some(connection_id);
+        }
+        Err(error) => {
+            error!("unable to reopen the connection: {error}");
+            commands.remove_resource::<Reconnecting>();
+            progress.write(ReconnectProgress::GaveUp {
+                attempts: reconnecting.attempt,
+            });
+        }
+    }
+}
+
+/// Maximum size of a datagram on the current connection, updated by [`RepliconQuinnetClientPlugin`].
+///
+/// Unreliable messages larger than this are rejected with a [`TransportSendError`], unless
+/// [`UnreliableFragmentation`] is enabled.
+/// Equals [`DEFAULT_INITIAL_MAX_SIZE`] until the connection reports a size.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxDatagramSize(pub usize);
+
+impl Default for MaxDatagramSize {
+    fn default() -> Self {
+        Self(DEFAULT_INITIAL_MAX_SIZE)
+    }
+}
+
+/// Returns `true` if the [`ActiveConnection`] is connected.
+pub fn active_connected(
+    quinnet_client: Option<Res<QuinnetClient>>,
+    active: Res<ActiveConnection>,
+) -> bool {
+    quinnet_client.is_some_and(|quinnet_client| {
+        active
+            .connection(&quinnet_client)
+            .is_some_and(|connection| connection.is_connected())
+    })
+}
+
+/// Answers the server's authentication challenge, see [`crate::auth`].
+///
+/// Must be inserted before connecting to servers with
+/// [`ServerAuth`](crate::server::ServerAuth). The [`ClientState`] stays
+/// [`ClientState::Connecting`] until the server accepts the response.
+#[derive(Resource)]
+pub struct ClientAuth {
+    /// Time the server has to accept the connection once it's established.
+    ///
+    /// The connection is then closed with [`DisconnectReason::AuthTimedOut`], for example when the
+    /// server doesn't require authentication.
+    pub timeout: Duration,
+    respond: Box<dyn Fn(&[u8]) -> Bytes + Send + Sync>,
+}
+
+impl ClientAuth {
+    /// Answers challenges with the output of `respond`.
+    pub fn new(respond: impl Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            respond: Box::new(move |challenge| respond(challenge).into()),
+        }
+    }
+
+    /// Answers every challenge with the same ticket.
+    pub fn ticket(ticket: impl Into<Bytes>) -> Self {
+        let ticket = ticket.into();
+        Self::new(move |_| ticket.to_vec())
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Authentication progress of the [`ActiveConnection`].
+#[derive(Resource, Default)]
+struct AuthState {
+    connection_id: Option<ConnectionLocalId>,
+    accepted: bool,
+    /// Time since the connection was established.
+    elapsed: Duration,
+    timed_out: bool,
+    /// Payloads of replicon channels received before the acceptance, streams are not ordered
+    /// relative to each other.
+    early: Vec<(u8, Bytes)>,
+}
+
+/// Answers challenges on the active connection until the server accepts it.
+fn process_auth(
+    mut quinnet_client: ResMut<QuinnetClient>,
+    mut auth_state: ResMut<AuthState>,
+    auth: Option<Res<ClientAuth>>,
+    mappings: Res<ChannelMappings>,
+    active: Res<ActiveConnection>,
+    time: Res<Time>,
+) {
+    let Some(auth) = auth else {
+        return;
+    };
+    let connection_id = active.resolve(&quinnet_client);
+    if auth_state.connection_id != connection_id {
+        *auth_state = AuthState {
+            connection_id,
+            ..Default::default()
+        };
+    }
+    if auth_state.accepted || auth_state.timed_out {
+        return;
+    }
+    let Some(connection) = active.connection_mut(&mut quinnet_client) else {
+        return;
+    };
+    if connection.is_connected() {
+        auth_state.elapsed += time.delta();
+        if auth_state.elapsed >= auth.timeout {
+            warn!(
+                "closing the connection after waiting {:?} for the server to accept it",
+                auth.timeout
             );
+            auth_state.timed_out = true;
+            if let Some(connection_id) = connection_id {
+                quinnet_client.close_connection(connection_id).ok();
+            }
+            return;
+        }
+    }
+
+    while let Ok((quinnet_channel, payload)) = connection.dequeue_undispatched_bytes_from_peer() {
+        if quinnet_channel != mappings.server.auth_channel() {
+            auth_state.early.push((quinnet_channel, payload));
+            continue;
+        }
+        match AuthMessage::decode(payload) {
+            Some(AuthMessage::Challenge(challenge)) => {
+                debug!("answering the server challenge");
+                let response = (auth.respond)(&challenge);
+                if let Err(err) =
+                    connection.send_payload_on(mappings.client.auth_channel(), response)
+                {
+                    warn!("unable to send the authentication response: {err}");
+                }
+            }
+            Some(AuthMessage::Accepted) => {
+                debug!("authenticated by the server");
+                auth_state.accepted = true;
+            }
+            None => warn!("dropping malformed authentication message"),
+        }
     }
 }
 
-fn set_disconnected(mut state: ResMut<NextState<ClientState>>) {
-    state.set(ClientState::Disconnected);
+/// Returns `true` if the [`ActiveConnection`] doesn't need to authenticate or was accepted.
+fn authenticated(auth: Option<Res<ClientAuth>>, auth_state: Res<AuthState>) -> bool {
+    auth.is_none() || auth_state.accepted
 }
 
-fn set_connecting(mut state: ResMut<NextState<ClientState>>) {
-    state.set(ClientState::Connecting);
+/// Written when replicon is disconnected from the server, see [`DisconnectReason`].
+#[derive(Message, Debug, Clone)]
+pub struct ServerDisconnected {
+    pub reason: DisconnectReason,
 }
 
-fn set_connected(mut state: ResMut<NextState<ClientState>>) {
-    state.set(ClientState::Connected);
+/// Derives [`ClientState`] from the [`ActiveConnection`].
+#[allow(clippy::too_many_arguments)]
+fn update_state(
+    mut lost_events: MessageReader<ConnectionLostEvent>,
+    mut failed_events: MessageReader<ConnectionFailedEvent>,
+    mut disconnects: MessageWriter<ServerDisconnected>,
+    mut next_state: ResMut<NextState<ClientState>>,
+    mut path_window: ResMut<PathStatsWindow>,
+    mut max_size: ResMut<MaxDatagramSize>,
+    mut fragments: ResMut<FragmentBuffer>,
+    mut last_connection: Local<Option<ConnectionLocalId>>,
+    mut lost_connection: Local<Option<ConnectionLocalId>>,
+    mut active: ResMut<ActiveConnection>,
+    quinnet_client: Res<QuinnetClient>,
+    state: Res<State<ClientState>>,
+    auth: Option<Res<ClientAuth>>,
+    auth_state: Res<AuthState>,
+) {
+    let connection_id = active.resolve(&quinnet_client);
+    let authenticated = auth.is_none() || auth_state.accepted;
+    for event_id in lost_events
+        .read()
+        .map(|event| event.id)
+        .chain(failed_events.read().map(|event| event.id))
+    {
+        if Some(event_id) == connection_id {
+            *lost_connection = connection_id;
+        }
+    }
+
+    let mut reason = DisconnectReason::Local;
+    let new_state = if *last_connection != connection_id && *state == ClientState::Connected {
+        debug!("switching replicon connection from {last_connection:?} to {connection_id:?}");
+        if active.handoff {
+            ClientState::Connecting
+        } else {
+            ClientState::Disconnected
+        }
+    } else {
+        *last_connection = connection_id;
+        if active.handoff {
+            active.bypass_change_detection().handoff = false;
+        }
+        match active.connection(&quinnet_client) {
+            Some(connection) if connection.is_connected() && authenticated => {
+                ClientState::Connected
+            }
+            Some(_) if *lost_connection != connection_id => ClientState::Connecting,
+            Some(connection) => {
+                reason = connection
+                    .quinn_close_reason()
+                    .map_or(DisconnectReason::Lost, |error| (&error).into());
+                ClientState::Disconnected
+            }
+            None => ClientState::Disconnected,
+        }
+    };
+
+    if new_state == ClientState::Disconnected && auth_state.timed_out {
+        reason = DisconnectReason::AuthTimedOut;
+    }
+    if new_state == ClientState::Disconnected && *state != ClientState::Disconnected {
+        debug!("disconnected from the server: {reason:?}");
+        disconnects.write(ServerDisconnected { reason });
+    }
+    if new_state == ClientState::Connected && *state != ClientState::Connected {
+        *lost_connection = None;
+        *path_window = Default::default();
+        *max_size = Default::default();
+        *fragments = Default::default();
+    }
+    if new_state != **state {
+        next_state.set(new_state);
+    }
 }
 
 fn update_statistics(
     mut bps_timer: Local<f64>,
     mut quinnet_client: ResMut<QuinnetClient>,
     mut client_stats: ResMut<ClientStats>,
+    mut path_window: ResMut<PathStatsWindow>,
+    mut max_size: ResMut<MaxDatagramSize>,
+    diagnostics: Option<ResMut<QuinnetConnectionDiagnostics>>,
+    active: Res<ActiveConnection>,
     time: Res<Time>,
 ) {
-    let Some(con) = quinnet_client.get_connection_mut() else {
+    let Some(con) = active.connection_mut(&mut quinnet_client) else {
         return;
     };
+    if let Some(size) = con.max_datagram_size() {
+        max_size.set_if_neq(MaxDatagramSize(size));
+    }
     let Some(quinn_stats) = con.quinn_connection_stats() else {
         return;
     };
 
+    if let Some(mut diagnostics) = diagnostics {
+        *diagnostics = (&quinn_stats).into();
+    }
+
     client_stats.rtt = quinn_stats.path.rtt.as_secs_f64();
-    client_stats.packet_loss =
-        100. * (quinn_stats.path.lost_packets as f64 / quinn_stats.path.sent_packets as f64);
+    if path_window.tick(time.delta_secs_f64()) {
+        path_window.push_sample(
+            quinn_stats.path.sent_packets,
+            quinn_stats.path.lost_packets,
+            quinn_stats.path.rtt,
+        );
+        client_stats.packet_loss = path_window.packet_loss();
+    }
 
     *bps_timer += time.delta_secs_f64();
     if *bps_timer >= BYTES_PER_SEC_PERIOD {
@@ -85,24 +906,150 @@ fn update_statistics(
     }
 }
 
+fn measure_diagnostics(
+    mut diagnostics: Diagnostics,
+    client_stats: Res<ClientStats>,
+    path_window: Res<PathStatsWindow>,
+) {
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::RTT, || client_stats.rtt);
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::JITTER, || {
+        path_window.jitter()
+    });
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::PACKET_LOSS, || {
+        client_stats.packet_loss
+    });
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::SENT_BPS, || {
+        client_stats.sent_bps
+    });
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::RECEIVED_BPS, || {
+        client_stats.received_bps
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
 fn receive_packets(
     mut quinnet_client: ResMut<QuinnetClient>,
+    mut auth_state: ResMut<AuthState>,
     mut messages: ResMut<ClientMessages>,
+    mut fragments: ResMut<FragmentBuffer>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    active: Res<ActiveConnection>,
+    time: Res<Time>,
 ) {
-    let Some(connection) = quinnet_client.get_connection_mut() else {
+    let Some(connection) = active.connection_mut(&mut quinnet_client) else {
         return;
     };
 
-    while let Ok((channel_id, message)) = connection.dequeue_undispatched_bytes_from_peer() {
+    let early = mem::take(&mut auth_state.early);
+    let received = iter::from_fn(|| connection.dequeue_undispatched_bytes_from_peer().ok());
+    for (quinnet_channel, payload) in early.into_iter().chain(received) {
+        let payload = if fragmentation.is_some()
+            && mappings.server.quinnet_channel(quinnet_channel) == Some(Channel::Unreliable)
+        {
+            let Some(payload) = fragments.reassemble(quinnet_channel, payload, time.elapsed())
+            else {
+                continue;
+            };
+            payload
+        } else {
+            payload
+        };
+        let Some((channel_id, message)) = mappings.server.decode(quinnet_channel, payload) else {
+            warn!("dropping malformed payload from the server on channel {quinnet_channel}");
+            continue;
+        };
         messages.insert_received(channel_id, message);
     }
+    if let Some(fragmentation) = fragmentation {
+        let expired = fragments.discard_expired(time.elapsed(), fragmentation.timeout);
+        if expired > 0 {
+            debug!("dropping {expired} incomplete messages from the server");
+        }
+    }
 }
 
-fn send_packets(mut quinnet_client: ResMut<QuinnetClient>, mut messages: ResMut<ClientMessages>) {
-    let Some(connection) = quinnet_client.get_connection_mut() else {
+#[allow(clippy::too_many_arguments)]
+fn send_packets(
+    mut quinnet_client: ResMut<QuinnetClient>,
+    mut messages: ResMut<ClientMessages>,
+    mut diagnostics: Diagnostics,
+    mut sent_messages: Local<Vec<usize>>,
+    mut send_errors: MessageWriter<TransportSendError>,
+    channel_paths: Res<ChannelDiagnosticPaths>,
+    policy: Res<SendErrorPolicy>,
+    mut fragments: ResMut<FragmentBuffer>,
+    mappings: Res<ChannelMappings>,
+    max_size: Res<MaxDatagramSize>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    active: Res<ActiveConnection>,
+) {
+    let Some(connection) = active.connection_mut(&mut quinnet_client) else {
         return;
     };
+    sent_messages.clear();
+    sent_messages.resize(channel_paths.0.len(), 0);
+    let mut disconnect = false;
+    let mut oversize = 0;
     for (channel_id, message) in messages.drain_sent() {
-        connection.try_send_payload_on(channel_id as u8, message);
+        if let Some(count) = sent_messages.get_mut(channel_id) {
+            *count += 1;
+        }
+        let payload_size = message.len();
+        let (quinnet_channel, payload) = mappings.client.encode(channel_id, message);
+        let unreliable =
+            mappings.client.quinnet_channel(quinnet_channel) == Some(Channel::Unreliable);
+        let max_payload_size = max_size.0.saturating_sub(QUINNET_DATAGRAM_HEADER_LEN);
+        let result = if unreliable && fragmentation.is_some() {
+            match fragments.split(payload, max_payload_size) {
+                Ok(fragments) => fragments
+                    .into_iter()
+                    .try_for_each(|fragment| connection.send_payload_on(quinnet_channel, fragment))
+                    .map_err(|err| SendErrorKind::Quinnet(err.into())),
+                Err(err) => {
+                    oversize += 1;
+                    Err(SendErrorKind::Fragmentation(err))
+                }
+            }
+        } else if unreliable && payload.len() > max_payload_size {
+            oversize += 1;
+            Err(SendErrorKind::Oversize {
+                size: payload.len(),
+                max_size: max_payload_size,
+            })
+        } else {
+            connection
+                .send_payload_on(quinnet_channel, payload)
+                .map_err(|err| SendErrorKind::Quinnet(err.into()))
+        };
+        if let Err(err) = result {
+            match *policy {
+                SendErrorPolicy::Ignore => (),
+                SendErrorPolicy::Warn => {
+                    warn!("unable to send {payload_size} bytes on channel {channel_id}: {err}");
+                }
+                SendErrorPolicy::Disconnect => {
+                    warn!("disconnecting after a send failure on channel {channel_id}: {err}");
+                    disconnect = true;
+                }
+            }
+            send_errors.write(TransportSendError {
+                client: None,
+                channel_id,
+                payload_size,
+                error: err,
+            });
+        }
+    }
+    if disconnect {
+        if let Some(connection_id) = active.resolve(&quinnet_client) {
+            quinnet_client.close_connection(connection_id).ok();
+        }
+    }
+    diagnostics.add_measurement(&RepliconQuinnetClientPlugin::OVERSIZE_MESSAGES, || {
+        oversize as f64
+    });
+    for (path, &count) in channel_paths.0.iter().zip(sent_messages.iter()) {
+        diagnostics.add_measurement(path, || count as f64);
     }
 }
@@ -0,0 +1,188 @@
+//! Opt-in fragmentation of unreliable payloads.
+//!
+//! Unreliable replicon channels are sent as QUIC datagrams, which can't exceed the maximum datagram
+//! size of the connection. When [`UnreliableFragmentation`] is inserted on both peers, every
+//! unreliable Quinnet payload is prefixed with a [`FRAGMENT_HEADER_LEN`] bytes header and payloads
+//! larger than a datagram are split into up to [`MAX_FRAGMENTS`] fragments.
+//!
+//! Fragments are reassembled on reception. If any fragment of a message is lost, the whole message is
+//! dropped once [`UnreliableFragmentation::timeout`] elapses.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+use bevy::ecs::{component::Component, resource::Resource};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Size of the header prepended to unreliable payloads: message id (`u16`), fragment index and
+/// fragment count (`u8` each).
+pub const FRAGMENT_HEADER_LEN: usize = size_of::<u16>() + 2 * size_of::<u8>();
+
+/// Maximum number of fragments of a single message.
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+/// Enables fragmentation of unreliable payloads when inserted.
+///
+/// Changes the wire format of unreliable channels, so it must be inserted on both the server and
+/// the clients before connecting.
+///
+/// On the server, [`ConnectedClient::max_size`](bevy_replicon::prelude::ConnectedClient::max_size)
+/// leaves room for the header, so messages packed up to it are sent in a single datagram.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct UnreliableFragmentation {
+    /// Time after which an incomplete message is dropped.
+    pub timeout: Duration,
+}
+
+impl Default for UnreliableFragmentation {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Error returned when a payload can't be split into fragments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The maximum datagram size leaves no room for fragment data.
+    DatagramTooSmall { max_size: usize },
+    /// The payload needs more than [`MAX_FRAGMENTS`] fragments.
+    PayloadTooLarge { size: usize, max_size: usize },
+}
+
+impl Display for FragmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DatagramTooSmall { max_size } => write!(
+                f,
+                "datagram size {max_size} is too small for the {FRAGMENT_HEADER_LEN} bytes fragment header"
+            ),
+            Self::PayloadTooLarge { size, max_size } => write!(
+                f,
+                "payload of {size} bytes exceeds {MAX_FRAGMENTS} fragments of {max_size} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FragmentError {}
+
+/// Fragmentation state of a single connection.
+///
+/// Inserted as a component on server clients and as a resource on the client.
+#[derive(Component, Resource, Debug, Default)]
+pub struct FragmentBuffer {
+    next_id: u16,
+    partials: HashMap<(u8, u16), PartialMessage>,
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    fragments: Vec<Option<Bytes>>,
+    received: usize,
+    started: Duration,
+}
+
+impl FragmentBuffer {
+    /// Splits a payload into fragments of at most `max_size` bytes, headers included.
+    pub fn split(&mut self, payload: Bytes, max_size: usize) -> Result<Vec<Bytes>, FragmentError> {
+        let chunk_size = max_size.saturating_sub(FRAGMENT_HEADER_LEN);
+        if chunk_size == 0 {
+            return Err(FragmentError::DatagramTooSmall { max_size });
+        }
+        let count = payload.len().div_ceil(chunk_size).max(1);
+        if count > MAX_FRAGMENTS {
+            return Err(FragmentError::PayloadTooLarge {
+                size: payload.len(),
+                max_size,
+            });
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let fragments = (0..count)
+            .map(|index| {
+                let start = index * chunk_size;
+                let end = payload.len().min(start + chunk_size);
+                let mut fragment = BytesMut::with_capacity(FRAGMENT_HEADER_LEN + end - start);
+                fragment.put_u16_le(id);
+                fragment.put_u8(index as u8);
+                fragment.put_u8(count as u8);
+                fragment.put(payload.slice(start..end));
+                fragment.freeze()
+            })
+            .collect();
+        Ok(fragments)
+    }
+
+    /// Adds a fragment received on `quinnet_channel` at time `now`.
+    ///
+    /// Returns the reassembled payload once all its fragments are received and `None` otherwise.
+    /// Malformed fragments are ignored.
+    pub fn reassemble(
+        &mut self,
+        quinnet_channel: u8,
+        mut fragment: Bytes,
+        now: Duration,
+    ) -> Option<Bytes> {
+        if fragment.len() < FRAGMENT_HEADER_LEN {
+            return None;
+        }
+        let id = fragment.get_u16_le();
+        let index = fragment.get_u8() as usize;
+        let count = fragment.get_u8() as usize;
+        if index >= count {
+            return None;
+        }
+        if count == 1 {
+            return Some(fragment);
+        }
+
+        let partial = self
+            .partials
+            .entry((quinnet_channel, id))
+            .or_insert_with(|| PartialMessage::new(count, now));
+        if partial.fragments.len() != count {
+            // Message id reused by a message with a different layout, the previous one is lost.
+            *partial = PartialMessage::new(count, now);
+        }
+        if partial.fragments[index].replace(fragment).is_none() {
+            partial.received += 1;
+        }
+        if partial.received < count {
+            return None;
+        }
+
+        let partial = self.partials.remove(&(quinnet_channel, id))?;
+        let size = partial.fragments.iter().flatten().map(Bytes::len).sum();
+        let mut payload = BytesMut::with_capacity(size);
+        for fragment in partial.fragments.into_iter().flatten() {
+            payload.put(fragment);
+        }
+        Some(payload.freeze())
+    }
+
+    /// Drops incomplete messages started before `now - timeout`.
+    ///
+    /// Returns the number of dropped messages.
+    pub fn discard_expired(&mut self, now: Duration, timeout: Duration) -> usize {
+        let len = self.partials.len();
+        self.partials
+            .retain(|_, partial| now.saturating_sub(partial.started) < timeout);
+        len - self.partials.len()
+    }
+}
+
+impl PartialMessage {
+    fn new(count: usize, started: Duration) -> Self {
+        Self {
+            fragments: vec![None; count],
+            received: 0,
+            started,
+        }
+    }
+}
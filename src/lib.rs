@@ -2,24 +2,199 @@
 Provides integration for [`bevy_replicon`](https://docs.rs/bevy_replicon) for [`bevy_quinnet`](https://docs.rs/bevy_quinnet).
 */
 
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::RangeInclusive,
+};
+
 use bevy::{app::PluginGroupBuilder, prelude::*};
-use bevy_quinnet::shared::channels::{
-    ChannelConfig, SendChannelsConfiguration, DEFAULT_MAX_RELIABLE_FRAME_LEN,
+use bevy_quinnet::shared::{
+    channels::{SendChannelsConfiguration, DEFAULT_MAX_RELIABLE_FRAME_LEN},
+    error::ConnectionSendError,
 };
 use bevy_replicon::prelude::*;
+use quinn_proto::ConnectionError;
 
+pub mod auth;
+pub mod channels;
 #[cfg(feature = "client")]
 pub mod client;
+pub mod fragmentation;
 #[cfg(feature = "server")]
 pub mod server;
+pub mod stats;
 
+use channels::{ChannelConfigError, ChannelMapping, ChannelMappings};
 #[cfg(feature = "client")]
 use client::RepliconQuinnetClientPlugin;
+use fragmentation::FragmentError;
 #[cfg(feature = "server")]
 use server::RepliconQuinnetServerPlugin;
 
 pub const BYTES_PER_SEC_PERIOD: f64 = 0.1;
 
+/// Maximum datagram size assumed before the connection reports one, the minimum supported by QUIC.
+pub const DEFAULT_INITIAL_MAX_SIZE: usize = 1200;
+
+/// Written when a replicon payload could not be queued on a Quinnet channel.
+#[derive(Message, Debug, Clone)]
+pub struct TransportSendError {
+    /// Client entity the payload was addressed to, `None` when sent by the client.
+    pub client: Option<Entity>,
+    pub channel_id: usize,
+    pub payload_size: usize,
+    pub error: SendErrorKind,
+}
+
+/// Why a payload could not be queued, see [`TransportSendError`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendErrorKind {
+    /// An unreliable payload exceeds the maximum datagram size and fragmentation is disabled.
+    Oversize { size: usize, max_size: usize },
+    /// An unreliable payload could not be split into fragments.
+    Fragmentation(FragmentError),
+    /// Quinnet refused the payload.
+    Quinnet(QuinnetSendError),
+}
+
+impl Display for SendErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Oversize { size, max_size } => write!(
+                f,
+                "unreliable payload of {size} bytes exceeds the maximum of {max_size}"
+            ),
+            Self::Fragmentation(error) => write!(f, "unable to fragment the payload: {error}"),
+            Self::Quinnet(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for SendErrorKind {}
+
+/// Why Quinnet refused a payload, mapped from its send errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuinnetSendError {
+    /// The Quinnet channel is closed.
+    ChannelClosed,
+    /// The connection is closed or no longer known by the endpoint.
+    ConnectionClosed,
+    /// Any other Quinnet error, formatted.
+    Other(String),
+}
+
+impl From<ConnectionSendError> for QuinnetSendError {
+    fn from(error: ConnectionSendError) -> Self {
+        match error {
+            ConnectionSendError::ChannelClosed => Self::ChannelClosed,
+            ConnectionSendError::ConnectionClosed => Self::ConnectionClosed,
+            error => Self::Other(error.to_string()),
+        }
+    }
+}
+
+impl Display for QuinnetSendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChannelClosed => write!(f, "channel is closed"),
+            Self::ConnectionClosed => write!(f, "connection is closed"),
+            Self::Other(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// What the backends do when a payload can't be queued, in addition to writing a [`TransportSendError`].
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SendErrorPolicy {
+    /// Only write the message.
+    Ignore,
+    /// Log a warning.
+    #[default]
+    Warn,
+    /// Log a warning and disconnect: the client entity on the server, or the connection on the client.
+    Disconnect,
+}
+
+/// Returns cryptographically secure random bytes from the operating system.
+///
+/// # Panics
+///
+/// Panics if the operating system doesn't provide a random number generator.
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).expect("operating system should provide random bytes");
+    bytes
+}
+
+/// Application close code sent to clients when the server shuts down.
+pub const SERVER_SHUTDOWN_CODE: u32 = 1;
+
+/// Default application close code sent to kicked clients.
+pub const KICKED_CODE: u32 = 2;
+
+/// Application close code sent to connections from banned addresses.
+pub const BANNED_CODE: u32 = 3;
+
+/// Default application close code sent to connections refused by admission control.
+pub const REJECTED_CODE: u32 = 4;
+
+/// Application close codes reported as [`DisconnectReason::Kicked`].
+///
+/// Includes [`KICKED_CODE`], [`BANNED_CODE`] and [`REJECTED_CODE`], the rest of the range is left
+/// for custom kick codes. Codes outside of this range and [`SERVER_SHUTDOWN_CODE`] are reported as
+/// [`DisconnectReason::Closed`].
+pub const KICK_CODES: RangeInclusive<u32> = KICKED_CODE..=0xFF;
+
+/// Why a connection ended.
+///
+/// Written with [`server::ClientDisconnected`] on the server and [`client::ServerDisconnected`]
+/// on the client. Inserted on a server client entity before despawning it to override
+/// [`DisconnectReason::Local`].
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection was closed locally.
+    Local,
+    /// The peer stopped responding.
+    TimedOut,
+    /// The peer closed the connection with an application code not defined by this crate.
+    Closed { code: u64, reason: String },
+    /// The server was shut down, see [`SERVER_SHUTDOWN_CODE`].
+    ServerShutdown,
+    /// The client was kicked or refused by the server with a code in [`KICK_CODES`].
+    Kicked { code: u64, reason: String },
+    /// The peers use incompatible QUIC versions or violated the transport protocol.
+    ProtocolMismatch,
+    /// The connection was lost without a known reason.
+    Lost,
+    /// The client closed the connection because the server didn't accept it within
+    /// [`client::ClientAuth::timeout`].
+    AuthTimedOut,
+}
+
+impl From<&ConnectionError> for DisconnectReason {
+    fn from(error: &ConnectionError) -> Self {
+        match error {
+            ConnectionError::ApplicationClosed(close) => {
+                let reason = String::from_utf8_lossy(&close.reason).into_owned();
+                match close.error_code.into_inner() {
+                    code if code == SERVER_SHUTDOWN_CODE as u64 => Self::ServerShutdown,
+                    code if u32::try_from(code).is_ok_and(|code| KICK_CODES.contains(&code)) => {
+                        Self::Kicked { code, reason }
+                    }
+                    code => Self::Closed { code, reason },
+                }
+            }
+            ConnectionError::TimedOut => Self::TimedOut,
+            ConnectionError::LocallyClosed => Self::Local,
+            ConnectionError::VersionMismatch
+            | ConnectionError::TransportError(_)
+            | ConnectionError::ConnectionClosed(_) => Self::ProtocolMismatch,
+            _ => Self::Lost,
+        }
+    }
+}
+
 pub struct RepliconQuinnetPlugins;
 
 impl PluginGroup for RepliconQuinnetPlugins {
@@ -28,66 +203,95 @@ impl PluginGroup for RepliconQuinnetPlugins {
 
         #[cfg(feature = "server")]
         {
-            group = group.add(RepliconQuinnetServerPlugin);
+            group = group.add(RepliconQuinnetServerPlugin::default());
         }
 
         #[cfg(feature = "client")]
         {
-            group = group.add(RepliconQuinnetClientPlugin);
+            group = group.add(RepliconQuinnetClientPlugin::default());
         }
 
         group
     }
 }
 
+/// Generates Quinnet channel configs for replicon channels.
+///
+/// Implemented for [`ChannelMappings`], which applies [`channels::QuinnetChannelOverrides`], and for
+/// [`RepliconChannels`], which ignores them. When starting the endpoint or opening the connection by
+/// hand with overrides, use the [`ChannelMappings`] resource, available once the app is finished.
+/// [`server::RepliconQuinnetServerConfig`] and [`client::RepliconQuinnetClientCommandsExt`] always
+/// apply the overrides.
 pub trait ChannelsConfigurationExt {
     /// Returns server channel configs that can be used to start an endpoint on the [`bevy_quinnet::server::QuinnetServer`].
-    fn server_configs(&self) -> SendChannelsConfiguration;
-
-    /// Same as [ChannelsConfigurationExt::server_configs] with custom configuration of `max_reliable_payload_size` used to configure Quinnet's [ChannelConfig]
-    fn server_configs_custom(&self, max_reliable_payload_size: usize) -> SendChannelsConfiguration;
-
-    /// Same as [`ChannelsConfigurationExt::server_configs`], but for clients.
-    fn client_configs(&self) -> SendChannelsConfiguration;
-
-    /// Same as [ChannelsConfigurationExt::client_configs] with custom configuration of `max_reliable_payload_size` used to configure Quinnet's [ChannelConfig]
-    fn client_configs_custom(&self, max_reliable_payload_size: usize) -> SendChannelsConfiguration;
-}
-impl ChannelsConfigurationExt for RepliconChannels {
     fn server_configs(&self) -> SendChannelsConfiguration {
         self.server_configs_custom(DEFAULT_MAX_RELIABLE_FRAME_LEN)
     }
 
+    /// Same as [ChannelsConfigurationExt::server_configs] with custom configuration of `max_reliable_payload_size` used to configure Quinnet's [ChannelConfig](bevy_quinnet::shared::channels::ChannelConfig)
+    ///
+    /// Channels beyond Quinnet's limit are multiplexed, see [`channels`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see [`ChannelsConfigurationExt::try_server_configs`].
     fn server_configs_custom(&self, max_reliable_payload_size: usize) -> SendChannelsConfiguration {
-        let channels = self.server_channels();
-        if channels.len() > u8::MAX as usize {
-            panic!("number of server channels shouldn't exceed `u8::MAX`");
-        }
-        create_configs(channels, max_reliable_payload_size)
+        self.try_server_configs(max_reliable_payload_size)
+            .unwrap_or_else(|e| panic!("invalid server channels configuration: {e}"))
     }
 
+    /// Fallible version of [`ChannelsConfigurationExt::server_configs_custom`].
+    fn try_server_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError>;
+
+    /// Same as [`ChannelsConfigurationExt::server_configs`], but for clients.
     fn client_configs(&self) -> SendChannelsConfiguration {
         self.client_configs_custom(DEFAULT_MAX_RELIABLE_FRAME_LEN)
     }
 
+    /// Same as [ChannelsConfigurationExt::server_configs_custom], but for clients.
     fn client_configs_custom(&self, max_reliable_payload_size: usize) -> SendChannelsConfiguration {
-        let channels = self.client_channels();
-        if channels.len() > u8::MAX as usize {
-            panic!("number of server channels shouldn't exceed `u8::MAX`");
-        }
-        create_configs(channels, max_reliable_payload_size)
+        self.try_client_configs(max_reliable_payload_size)
+            .unwrap_or_else(|e| panic!("invalid client channels configuration: {e}"))
+    }
+
+    /// Fallible version of [`ChannelsConfigurationExt::client_configs_custom`].
+    fn try_client_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError>;
+}
+
+impl ChannelsConfigurationExt for RepliconChannels {
+    fn try_server_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError> {
+        ChannelMapping::new(self.server_channels()).quinnet_configs(max_reliable_payload_size)
+    }
+
+    fn try_client_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError> {
+        ChannelMapping::new(self.client_channels()).quinnet_configs(max_reliable_payload_size)
     }
 }
 
-/// Converts replicon channels into quinnet channel configs.
-fn create_configs(channels: &[Channel], max_frame_size: usize) -> SendChannelsConfiguration {
-    let mut quinnet_channels = SendChannelsConfiguration::new();
-    for channel in channels.iter() {
-        quinnet_channels.add(match channel {
-            Channel::Unreliable => ChannelConfig::Unreliable,
-            Channel::Unordered => ChannelConfig::UnorderedReliable { max_frame_size },
-            Channel::Ordered => ChannelConfig::OrderedReliable { max_frame_size },
-        });
+impl ChannelsConfigurationExt for ChannelMappings {
+    fn try_server_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError> {
+        self.server().quinnet_configs(max_reliable_payload_size)
+    }
+
+    fn try_client_configs(
+        &self,
+        max_reliable_payload_size: usize,
+    ) -> Result<SendChannelsConfiguration, ChannelConfigError> {
+        self.client().quinnet_configs(max_reliable_payload_size)
     }
-    quinnet_channels
 }
@@ -1,46 +1,160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
 use bevy::{
     app::{App, Plugin, PostUpdate, PreUpdate},
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
     ecs::{
+        component::Component,
         entity::Entity,
         lifecycle::Remove,
-        message::MessageReader,
+        message::{Message, MessageReader, MessageWriter},
         observer::On,
-        schedule::IntoScheduleConfigs,
+        query::{Has, QueryData, With, Without},
+        resource::Resource,
+        schedule::{
+            common_conditions::{resource_added, resource_exists},
+            IntoScheduleConfigs,
+        },
         system::{Commands, Query},
     },
-    log::debug,
-    prelude::{Local, Res, ResMut},
+    log::{debug, error, warn},
+    prelude::{Res, ResMut},
     state::state::NextState,
     time::Time,
 };
 use bevy_quinnet::{
-    server::{QuinnetServer, QuinnetServerPlugin},
-    shared::QuinnetSyncPreUpdate,
+    server::{
+        certificate::CertificateRetrievalMode, Endpoint, EndpointAddrConfiguration, QuinnetServer,
+        QuinnetServerPlugin, ServerEndpointConfiguration, ServerEndpointConfigurationDefaultables,
+    },
+    shared::{
+        channels::DEFAULT_MAX_RELIABLE_FRAME_LEN, error::EndpointSendError, ClientId,
+        QuinnetSyncPreUpdate,
+    },
 };
 use bevy_replicon::{
-    prelude::{ClientStats, ConnectedClient, DisconnectRequest, ServerMessages, ServerState},
+    prelude::{
+        Channel, ClientStats, ConnectedClient, DisconnectRequest, ServerMessages, ServerState,
+    },
     server::ServerSystems,
     shared::backend::connected_client::{NetworkId, NetworkIdMap},
 };
+use bytes::Bytes;
+use quinn_proto::VarInt;
+
+use crate::{
+    auth::{AuthMessage, CHALLENGE_LEN},
+    channels::ChannelMappings,
+    fragmentation::{FragmentBuffer, UnreliableFragmentation, FRAGMENT_HEADER_LEN},
+    random_bytes,
+    stats::{BandwidthWindow, PathStatsWindow, QuinnetConnectionDiagnostics},
+    DisconnectReason, QuinnetSendError, SendErrorKind, SendErrorPolicy, TransportSendError,
+    BANNED_CODE, DEFAULT_INITIAL_MAX_SIZE, KICKED_CODE, KICK_CODES, REJECTED_CODE,
+    SERVER_SHUTDOWN_CODE,
+};
+
+pub struct RepliconQuinnetServerPlugin {
+    /// [`ConnectedClient::max_size`] used when the connection doesn't report a maximum datagram size yet.
+    pub initial_max_size: usize,
+    /// Maximum time a despawned client connection is kept open to deliver its pending data.
+    ///
+    /// Delivery is not confirmed: connections are closed earlier once nothing was transmitted for
+    /// 3 RTTs, which is only a heuristic. Retransmission backoff on a lossy link can exceed that
+    /// gap, and data still queued in Quinnet is not observed.
+    pub disconnect_grace_period: Duration,
+}
 
-use crate::BYTES_PER_SEC_PERIOD;
+impl Default for RepliconQuinnetServerPlugin {
+    fn default() -> Self {
+        Self {
+            initial_max_size: DEFAULT_INITIAL_MAX_SIZE,
+            disconnect_grace_period: Duration::from_secs(1),
+        }
+    }
+}
 
-pub struct RepliconQuinnetServerPlugin;
+impl RepliconQuinnetServerPlugin {
+    /// Number of connected clients.
+    pub const CONNECTED_CLIENTS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/connected_clients");
+    /// Average round-trip time of the connected clients, in seconds.
+    pub const AVERAGE_RTT: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/average_rtt");
+    /// Average percentage of packets lost by the connected clients.
+    pub const AVERAGE_PACKET_LOSS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/average_packet_loss");
+    /// Average RTT jitter of the connected clients over their [`PathStatsWindow`], in seconds.
+    pub const AVERAGE_JITTER: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/average_jitter");
+    /// Bytes sent per second to all clients.
+    pub const SENT_BPS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/sent_bps");
+    /// Bytes received per second from all clients.
+    pub const RECEIVED_BPS: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/received_bps");
+    /// Number of messages dropped during the last update because their client was despawned.
+    pub const DROPPED_MESSAGES: DiagnosticPath =
+        DiagnosticPath::const_new("replicon_quinnet/server/dropped_messages");
+}
 
 impl Plugin for RepliconQuinnetServerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(QuinnetServerPlugin::default())
+            .add_message::<ServerStartFailed>()
+            .add_message::<TransportSendError>()
+            .add_message::<MaxSizeChanged>()
+            .add_message::<ClientDisconnected>()
+            .add_message::<ShutdownServer>()
+            .add_message::<KickClient>()
+            .init_resource::<SendErrorPolicy>()
+            .init_resource::<DroppedMessages>()
+            .init_resource::<PendingAuths>()
+            .register_required_components::<ConnectedClient, FragmentBuffer>()
+            .register_required_components::<ConnectedClient, BandwidthWindow>()
+            .register_required_components::<ConnectedClient, PathStatsWindow>()
+            .insert_resource(InitialMaxSize(self.initial_max_size))
+            .insert_resource(PendingDisconnects::new(self.disconnect_grace_period))
+            .register_diagnostic(Diagnostic::new(Self::CONNECTED_CLIENTS))
+            .register_diagnostic(Diagnostic::new(Self::AVERAGE_RTT).with_suffix(" s"))
+            .register_diagnostic(Diagnostic::new(Self::AVERAGE_PACKET_LOSS).with_suffix(" %"))
+            .register_diagnostic(Diagnostic::new(Self::AVERAGE_JITTER).with_suffix(" s"))
+            .register_diagnostic(Diagnostic::new(Self::SENT_BPS).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::RECEIVED_BPS).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::DROPPED_MESSAGES))
             .configure_sets(
                 PreUpdate,
                 ServerSystems::ReceivePackets.after(QuinnetSyncPreUpdate),
             )
             .add_observer(disconnect_client)
+            .add_systems(
+                PreUpdate,
+                cache_close_reasons
+                    .before(QuinnetSyncPreUpdate)
+                    .run_if(bevy_quinnet::server::server_listening),
+            )
+            .add_systems(
+                PreUpdate,
+                start_endpoint
+                    .run_if(resource_added::<RepliconQuinnetServerConfig>)
+                    .before(ServerSystems::ReceivePackets),
+            )
             .add_systems(
                 PreUpdate,
                 (
                     set_running.run_if(bevy_quinnet::server::server_just_opened),
                     set_stopped.run_if(bevy_quinnet::server::server_just_closed),
-                    (receive_packets, update_statistics, process_server_events)
+                    (
+                        receive_packets,
+                        update_statistics,
+                        process_server_events,
+                        process_auth.after(process_server_events),
+                        reconcile_connections.after(process_server_events),
+                        measure_diagnostics.after(update_statistics),
+                    )
                         .run_if(bevy_quinnet::server::server_listening),
                 )
                     .in_set(ServerSystems::ReceivePackets),
@@ -52,111 +166,738 @@ impl Plugin for RepliconQuinnetServerPlugin {
                         .in_set(ServerSystems::SendPackets)
                         .run_if(bevy_quinnet::server::server_listening),
                     disconnect_by_request.after(ServerSystems::SendPackets),
+                    kick_clients
+                        .after(ServerSystems::SendPackets)
+                        .before(disconnect_by_request),
+                    (
+                        start_shutdown,
+                        drain_shutdown.run_if(resource_exists::<ShuttingDown>),
+                        drain_disconnects,
+                    )
+                        .chain()
+                        .after(disconnect_by_request)
+                        .after(kick_clients)
+                        .run_if(bevy_quinnet::server::server_listening),
                 ),
             );
     }
+
+    fn finish(&self, app: &mut App) {
+        ChannelMappings::init(app);
+    }
+}
+
+/// Declarative configuration of the server endpoint.
+///
+/// When this resource is inserted, [`RepliconQuinnetServerPlugin`] starts the [`QuinnetServer`] endpoint
+/// with send channels derived from [`RepliconChannels`], so it should be inserted after all replicon
+/// messages and events have been registered (from a startup system for example).
+///
+/// [`RepliconChannels`]: bevy_replicon::prelude::RepliconChannels
+///
+/// If the endpoint fails to start, a [`ServerStartFailed`] message is written instead of panicking.
+#[derive(Resource, Debug, Clone)]
+pub struct RepliconQuinnetServerConfig {
+    /// Local address the endpoint listens on.
+    pub addr_config: EndpointAddrConfiguration,
+    /// How the server certificate is retrieved.
+    pub cert_mode: CertificateRetrievalMode,
+    /// Maximum frame size of the reliable Quinnet channels, see [`crate::ChannelsConfigurationExt::server_configs_custom`].
+    pub max_reliable_payload_size: usize,
+}
+
+impl RepliconQuinnetServerConfig {
+    pub fn new(
+        addr_config: EndpointAddrConfiguration,
+        cert_mode: CertificateRetrievalMode,
+    ) -> Self {
+        Self {
+            addr_config,
+            cert_mode,
+            max_reliable_payload_size: DEFAULT_MAX_RELIABLE_FRAME_LEN,
+        }
+    }
+
+    pub fn with_max_reliable_payload_size(mut self, max_reliable_payload_size: usize) -> Self {
+        self.max_reliable_payload_size = max_reliable_payload_size;
+        self
+    }
+}
+
+/// Written when the endpoint described by [`RepliconQuinnetServerConfig`] could not be started.
+#[derive(Message, Debug, Clone)]
+pub struct ServerStartFailed {
+    pub error: String,
+}
+
+fn start_endpoint(
+    config: Res<RepliconQuinnetServerConfig>,
+    mappings: Res<ChannelMappings>,
+    mut quinnet_server: ResMut<QuinnetServer>,
+    mut start_errors: MessageWriter<ServerStartFailed>,
+) {
+    let send_channels_cfg = match mappings
+        .server
+        .quinnet_configs(config.max_reliable_payload_size)
+    {
+        Ok(send_channels_cfg) => send_channels_cfg,
+        Err(err) => {
+            error!("unable to start the server endpoint: {err}");
+            start_errors.write(ServerStartFailed {
+                error: err.to_string(),
+            });
+            return;
+        }
+    };
+    if let Err(err) = quinnet_server.start_endpoint(ServerEndpointConfiguration {
+        addr_config: config.addr_config.clone(),
+        cert_mode: config.cert_mode.clone(),
+        defaultables: ServerEndpointConfigurationDefaultables { send_channels_cfg },
+    }) {
+        error!("unable to start the server endpoint: {err}");
+        start_errors.write(ServerStartFailed {
+            error: err.to_string(),
+        });
+    }
 }
 
 fn set_running(mut state: ResMut<NextState<ServerState>>) {
     state.set(ServerState::Running);
 }
 
-fn set_stopped(mut state: ResMut<NextState<ServerState>>) {
+fn set_stopped(
+    mut commands: Commands,
+    mut state: ResMut<NextState<ServerState>>,
+    mut pending: ResMut<PendingDisconnects>,
+    mut pending_auths: ResMut<PendingAuths>,
+) {
+    // Connections of the stopped endpoint are already closed.
+    pending.disconnects.clear();
+    pending_auths.0.clear();
+    // The endpoint could have been stopped before the end of a shutdown.
+    commands.remove_resource::<ShuttingDown>();
     state.set(ServerState::Stopped);
 }
 
+/// Value of [`RepliconQuinnetServerPlugin::initial_max_size`].
+#[derive(Resource)]
+struct InitialMaxSize(usize);
+
+/// Written when the maximum datagram size of a client connection changes.
+///
+/// [`ConnectedClient::max_size`] is already updated when this message is written.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct MaxSizeChanged {
+    pub client: Entity,
+    pub old_size: usize,
+    pub new_size: usize,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_server_events(
     mut commands: Commands,
     mut conn_events: MessageReader<bevy_quinnet::server::ConnectionEvent>,
     mut conn_lost_events: MessageReader<bevy_quinnet::server::ConnectionLostEvent>,
+    mut quinnet_server: ResMut<QuinnetServer>,
     network_map: Res<NetworkIdMap>,
+    initial_max_size: Res<InitialMaxSize>,
+    shutting_down: Option<Res<ShuttingDown>>,
+    ban_list: Option<Res<BanList>>,
+    admission: Option<Res<AdmissionControl>>,
+    auth: Option<Res<ServerAuth>>,
+    mut pending_auths: ResMut<PendingAuths>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    clients: Query<&NetworkId, With<ConnectedClient>>,
+    closed_clients: Query<(), With<DisconnectReason>>,
 ) {
+    // Addresses of admitted clients, including the ones admitted by this system.
+    let mut client_addrs = Vec::new();
+    if admission.is_some() && !conn_events.is_empty() {
+        if let Some(endpoint) = quinnet_server.get_endpoint() {
+            client_addrs.extend(
+                clients
+                    .iter()
+                    .filter_map(|network_id| endpoint.connection(network_id.get()))
+                    .map(|con| con.remote_addr().ip()),
+            );
+        }
+        client_addrs.extend(
+            pending_auths
+                .0
+                .values()
+                .map(|pending| pending.remote_addr.ip()),
+        );
+    }
+
     for event in conn_events.read() {
-        let network_id = NetworkId::new(event.id);
-        const DEFAULT_INITIAL_MAX_DATAGRAM_SIZE: usize = 1200;
-        commands.spawn((
-            ConnectedClient {
-                max_size: DEFAULT_INITIAL_MAX_DATAGRAM_SIZE,
-            },
-            network_id,
-        ));
+        let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
+            continue;
+        };
+        if shutting_down.is_some() {
+            debug!("refusing connection {} during shutdown", event.id);
+            close_connection(endpoint, event.id, &DisconnectReason::ServerShutdown);
+            continue;
+        }
+        let Some(remote_addr) = endpoint.connection(event.id).map(|con| con.remote_addr()) else {
+            debug!(
+                "ignoring connection {} closed before being processed",
+                event.id
+            );
+            continue;
+        };
+        if ban_list
+            .as_ref()
+            .is_some_and(|ban_list| ban_list.contains(remote_addr.ip()))
+        {
+            debug!("refusing connection {} from a banned address", event.id);
+            let reason = DisconnectReason::Kicked {
+                code: BANNED_CODE.into(),
+                reason: "banned".into(),
+            };
+            close_connection(endpoint, event.id, &reason);
+            continue;
+        }
+        if let Some(admission) = &admission {
+            let request = AdmissionRequest {
+                client_id: event.id,
+                remote_addr,
+                clients: client_addrs.len(),
+                clients_from_ip: client_addrs
+                    .iter()
+                    .filter(|&&ip| ip == remote_addr.ip())
+                    .count(),
+            };
+            if let Err(rejection) = admission.check(&request) {
+                debug!(
+                    "refusing connection {} from {remote_addr}: {}",
+                    event.id, rejection.reason
+                );
+                let reason = kick_reason(rejection.code, rejection.reason);
+                close_connection(endpoint, event.id, &reason);
+                continue;
+            }
+            client_addrs.push(remote_addr.ip());
+        }
+        if let Some(auth) = &auth {
+            debug!("challenging connection {}", event.id);
+            let challenge = random_bytes::<CHALLENGE_LEN>();
+            let message = AuthMessage::Challenge(Bytes::copy_from_slice(&challenge));
+            if let Err(err) =
+                endpoint.send_payload_on(event.id, mappings.server.auth_channel(), message.encode())
+            {
+                warn!("unable to send the challenge to {}: {err}", event.id);
+            }
+            pending_auths.0.insert(
+                event.id,
+                PendingAuth {
+                    remote_addr,
+                    challenge,
+                    remaining: auth.timeout,
+                },
+            );
+            continue;
+        }
+
+        spawn_client(
+            &mut commands,
+            endpoint,
+            &mappings,
+            fragmentation.is_some(),
+            event.id,
+            initial_max_size.0,
+        );
     }
     for event in conn_lost_events.read() {
         let network_id = NetworkId::new(event.id);
         if let Some(&client_entity) = network_map.get(&network_id) {
+            let reason = quinnet_server
+                .get_endpoint()
+                .and_then(|endpoint| endpoint.connection(event.id))
+                .and_then(|con| con.quinn_close_reason())
+                .map(|error| DisconnectReason::from(&error));
             // Entity could have been despawned by user.
-            commands.entity(client_entity).despawn();
+            let mut entity = commands.entity(client_entity);
+            match reason {
+                Some(reason) => {
+                    entity.try_insert(reason);
+                }
+                None if !closed_clients.contains(client_entity) => {
+                    entity.try_insert(DisconnectReason::Lost);
+                }
+                None => (),
+            }
+            entity.try_despawn();
+        }
+    }
+}
+
+/// Stores the close reason of closed client connections.
+///
+/// Quinnet removes lost connections before writing [`ConnectionLostEvent`](bevy_quinnet::server::ConnectionLostEvent),
+/// so the reason is no longer available when the client entity is despawned.
+fn cache_close_reasons(
+    mut commands: Commands,
+    quinnet_server: Res<QuinnetServer>,
+    clients: Query<(Entity, &NetworkId), (With<ConnectedClient>, Without<DisconnectReason>)>,
+) {
+    let Some(endpoint) = quinnet_server.get_endpoint() else {
+        return;
+    };
+    for (client_entity, network_id) in &clients {
+        if let Some(error) = endpoint
+            .connection(network_id.get())
+            .and_then(|con| con.quinn_close_reason())
+        {
+            commands
+                .entity(client_entity)
+                .try_insert(DisconnectReason::from(&error));
+        }
+    }
+}
+
+/// Spawns the replicon client of an admitted connection.
+fn spawn_client(
+    commands: &mut Commands,
+    endpoint: &mut Endpoint,
+    mappings: &ChannelMappings,
+    fragmentation: bool,
+    client_id: ClientId,
+    initial_max_size: usize,
+) {
+    let max_size = endpoint
+        .connection_mut(client_id)
+        .and_then(|con| con.max_datagram_size())
+        .map_or(initial_max_size, |datagram_size| {
+            client_max_size(datagram_size, mappings, fragmentation)
+        });
+    commands.spawn((ConnectedClient { max_size }, NetworkId::new(client_id)));
+}
+
+/// Returns the [`ConnectedClient::max_size`] of a connection, without the headers added by
+/// [`send_packets`] to unreliable payloads.
+fn client_max_size(datagram_size: usize, mappings: &ChannelMappings, fragmentation: bool) -> usize {
+    let max_size = mappings.server.max_message_size(datagram_size);
+    if fragmentation {
+        max_size.saturating_sub(FRAGMENT_HEADER_LEN)
+    } else {
+        max_size
+    }
+}
+
+/// Requires clients to authenticate before spawning their [`ConnectedClient`], see [`crate::auth`].
+///
+/// Checked after [`AdmissionControl`] when inserted. Clients must insert
+/// [`ClientAuth`](crate::client::ClientAuth).
+#[derive(Resource)]
+pub struct ServerAuth {
+    /// Time a client has to answer the challenge.
+    pub timeout: Duration,
+    validator: Box<AuthValidator>,
+}
+
+/// Decides whether an [`AuthRequest`] is accepted, see [`ServerAuth`].
+pub type AuthValidator =
+    dyn Fn(&AuthRequest) -> Result<(), AdmissionRejection> + Send + Sync + 'static;
+
+impl ServerAuth {
+    pub fn new(
+        validator: impl Fn(&AuthRequest) -> Result<(), AdmissionRejection> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            validator: Box::new(validator),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Response of a connection to its challenge, validated by [`ServerAuth`].
+#[derive(Debug, Clone, Copy)]
+pub struct AuthRequest<'a> {
+    pub client_id: ClientId,
+    pub remote_addr: SocketAddr,
+    /// Unique challenge sent to the client.
+    ///
+    /// Generated by the operating system's cryptographically secure random number generator.
+    pub challenge: &'a [u8],
+    /// Response produced by [`ClientAuth`](crate::client::ClientAuth).
+    pub response: &'a [u8],
+}
+
+/// Connections waiting for their response to be validated by [`ServerAuth`].
+#[derive(Resource, Default)]
+struct PendingAuths(HashMap<ClientId, PendingAuth>);
+
+struct PendingAuth {
+    remote_addr: SocketAddr,
+    challenge: [u8; CHALLENGE_LEN],
+    remaining: Duration,
+}
+
+/// Validates responses of pending connections and spawns the accepted clients.
+#[allow(clippy::too_many_arguments)]
+fn process_auth(
+    mut commands: Commands,
+    mut quinnet_server: ResMut<QuinnetServer>,
+    mut pending_auths: ResMut<PendingAuths>,
+    auth: Option<Res<ServerAuth>>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    initial_max_size: Res<InitialMaxSize>,
+    shutting_down: Option<Res<ShuttingDown>>,
+    time: Res<Time>,
+) {
+    let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
+        return;
+    };
+    pending_auths.0.retain(|&client_id, pending| {
+        if shutting_down.is_some() {
+            close_connection(endpoint, client_id, &DisconnectReason::ServerShutdown);
+            return false;
+        }
+        let Some(con) = endpoint.connection_mut(client_id) else {
+            debug!("connection {client_id} closed during authentication");
+            return false;
+        };
+        let mut response = None;
+        while let Ok((quinnet_channel, payload)) = con.dequeue_undispatched_bytes_from_peer() {
+            if quinnet_channel == mappings.client.auth_channel() {
+                response = Some(payload);
+                break;
+            }
+            debug!("dropping payload from unauthenticated connection {client_id} on channel {quinnet_channel}");
+        }
+
+        let result = match (&auth, response) {
+            (Some(auth), Some(response)) => (auth.validator)(&AuthRequest {
+                client_id,
+                remote_addr: pending.remote_addr,
+                challenge: &pending.challenge,
+                response: &response,
+            }),
+            (Some(_), None) => {
+                pending.remaining = pending.remaining.saturating_sub(time.delta());
+                if !pending.remaining.is_zero() {
+                    return true;
+                }
+                Err(AdmissionRejection::new("authentication timed out"))
+            }
+            (None, _) => Err(AdmissionRejection::new("authentication disabled")),
+        };
+        match result {
+            Ok(()) => {
+                debug!("connection {client_id} authenticated");
+                let accepted = AuthMessage::Accepted.encode();
+                if let Err(err) =
+                    endpoint.send_payload_on(client_id, mappings.server.auth_channel(), accepted)
+                {
+                    warn!("unable to accept connection {client_id}: {err}");
+                }
+                spawn_client(
+                    &mut commands,
+                    endpoint,
+                    &mappings,
+                    fragmentation.is_some(),
+                    client_id,
+                    initial_max_size.0,
+                );
+            }
+            Err(rejection) => {
+                debug!(
+                    "refusing connection {client_id} from {}: {}",
+                    pending.remote_addr, rejection.reason
+                );
+                let reason = kick_reason(rejection.code, rejection.reason);
+                close_connection(endpoint, client_id, &reason);
+            }
+        }
+        false
+    });
+}
+
+/// Counters of messages dropped by [`RepliconQuinnetServerPlugin`] instead of being sent.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct DroppedMessages {
+    /// Messages addressed to client entities despawned before they could be sent.
+    pub vanished_clients: u64,
+}
+
+/// Marks a connected client whose [`NetworkId`] has no matching Quinnet connection.
+///
+/// Inserted by [`RepliconQuinnetServerPlugin`] when the connection disappeared without a
+/// [`bevy_quinnet::server::ConnectionLostEvent`]. The client entity is despawned on the next update
+/// if the connection is still missing.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct StaleConnection;
+
+/// Reconciles client entities from [`NetworkIdMap`] with the clients known by the endpoint.
+fn reconcile_connections(
+    mut commands: Commands,
+    quinnet_server: Res<QuinnetServer>,
+    clients: Query<(Entity, &NetworkId, Has<StaleConnection>), With<ConnectedClient>>,
+) {
+    let Some(endpoint) = quinnet_server.get_endpoint() else {
+        return;
+    };
+    let connected_ids = endpoint.clients();
+    for (client_entity, network_id, stale) in &clients {
+        if connected_ids.contains(&network_id.get()) {
+            if stale {
+                commands.entity(client_entity).remove::<StaleConnection>();
+            }
+        } else if stale {
+            debug!("despawning client `{client_entity}` without connection");
+            commands
+                .entity(client_entity)
+                .try_insert(DisconnectReason::Lost)
+                .try_despawn();
+        } else {
+            warn!(
+                "client `{client_entity}` has no connection for id {}",
+                network_id.get()
+            );
+            commands.entity(client_entity).insert(StaleConnection);
         }
     }
 }
 
+/// Client components updated by [`update_statistics`].
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct ClientStatistics {
+    entity: Entity,
+    network_id: &'static NetworkId,
+    client: &'static mut ConnectedClient,
+    stats: &'static mut ClientStats,
+    bandwidth: &'static mut BandwidthWindow,
+    path_window: &'static mut PathStatsWindow,
+    diagnostics: Option<&'static mut QuinnetConnectionDiagnostics>,
+}
+
 fn update_statistics(
-    mut bps_timer: Local<f64>,
-    mut clients: Query<(&NetworkId, &mut ConnectedClient, &mut ClientStats)>,
+    mut clients: Query<ClientStatistics>,
     mut quinnet_server: ResMut<QuinnetServer>,
+    mut size_changes: MessageWriter<MaxSizeChanged>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
     time: Res<Time>,
 ) {
     let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
         return;
     };
-    for (network_id, mut client, mut client_stats) in clients.iter_mut() {
-        let Some(con) = endpoint.connection_mut(network_id.get()) else {
-            return;
+    for mut client in clients.iter_mut() {
+        let Some(con) = endpoint.connection_mut(client.network_id.get()) else {
+            // Flagged by `reconcile_connections`.
+            continue;
         };
 
-        if let Some(max_size) = con.max_datagram_size() {
-            client.max_size = max_size;
+        if let Some(datagram_size) = con.max_datagram_size() {
+            let max_size = client_max_size(datagram_size, &mappings, fragmentation.is_some());
+            if client.client.max_size != max_size {
+                debug!(
+                    "max size of client `{}` changed from {} to {max_size}",
+                    client.entity, client.client.max_size
+                );
+                size_changes.write(MaxSizeChanged {
+                    client: client.entity,
+                    old_size: client.client.max_size,
+                    new_size: max_size,
+                });
+                client.client.max_size = max_size;
+            }
         }
 
         let quinn_stats = con.quinn_connection_stats();
+        if let Some(diagnostics) = &mut client.diagnostics {
+            **diagnostics = (&quinn_stats).into();
+        }
 
-        client_stats.rtt = quinn_stats.path.rtt.as_secs_f64();
-        client_stats.packet_loss =
-            100. * (quinn_stats.path.lost_packets as f64 / quinn_stats.path.sent_packets as f64);
+        client.stats.rtt = quinn_stats.path.rtt.as_secs_f64();
+        if client.path_window.tick(time.delta_secs_f64()) {
+            client.path_window.push_sample(
+                quinn_stats.path.sent_packets,
+                quinn_stats.path.lost_packets,
+                quinn_stats.path.rtt,
+            );
+            client.stats.packet_loss = client.path_window.packet_loss();
+        }
 
-        *bps_timer += time.delta_secs_f64();
-        if *bps_timer >= BYTES_PER_SEC_PERIOD {
-            *bps_timer = 0.;
+        if client.bandwidth.tick(time.delta_secs_f64()) {
             let stats = con.stats_mut();
-            let received_bytes_count = stats.clear_received_bytes_count() as f64;
-            let sent_bytes_count = stats.clear_sent_bytes_count() as f64;
-            client_stats.received_bps = received_bytes_count / BYTES_PER_SEC_PERIOD;
-            client_stats.sent_bps = sent_bytes_count / BYTES_PER_SEC_PERIOD;
+            let received_bytes_count = stats.clear_received_bytes_count() as u64;
+            let sent_bytes_count = stats.clear_sent_bytes_count() as u64;
+            client
+                .bandwidth
+                .push_sample(sent_bytes_count, received_bytes_count);
+            client.stats.received_bps = client.bandwidth.received_bps();
+            client.stats.sent_bps = client.bandwidth.sent_bps();
         }
     }
 }
 
+fn measure_diagnostics(
+    mut diagnostics: Diagnostics,
+    clients: Query<(&ClientStats, &PathStatsWindow), With<ConnectedClient>>,
+) {
+    let clients_count = clients.iter().len();
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::CONNECTED_CLIENTS, || {
+        clients_count as f64
+    });
+    if clients_count == 0 {
+        return;
+    }
+
+    let mut total = ClientStats::default();
+    let mut total_jitter = 0.0;
+    for (client_stats, path_window) in &clients {
+        total_jitter += path_window.jitter();
+        total.rtt += client_stats.rtt;
+        total.packet_loss += client_stats.packet_loss;
+        total.sent_bps += client_stats.sent_bps;
+        total.received_bps += client_stats.received_bps;
+    }
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::AVERAGE_RTT, || {
+        total.rtt / clients_count as f64
+    });
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::AVERAGE_PACKET_LOSS, || {
+        total.packet_loss / clients_count as f64
+    });
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::AVERAGE_JITTER, || {
+        total_jitter / clients_count as f64
+    });
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::SENT_BPS, || total.sent_bps);
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::RECEIVED_BPS, || {
+        total.received_bps
+    });
+}
+
 fn receive_packets(
     mut quinnet_server: ResMut<QuinnetServer>,
     mut messages: ResMut<ServerMessages>,
-    mut clients: Query<(Entity, &NetworkId)>,
+    mut clients: Query<(Entity, &NetworkId, &mut FragmentBuffer)>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    time: Res<Time>,
 ) {
     let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
         return;
     };
-    for (client_entity, network_id) in &mut clients {
+    for (client_entity, network_id, mut fragments) in &mut clients {
         let Some(con) = endpoint.connection_mut(network_id.get()) else {
             continue;
         };
-        while let Ok((channel_id, message)) = con.dequeue_undispatched_bytes_from_peer() {
+        while let Ok((quinnet_channel, payload)) = con.dequeue_undispatched_bytes_from_peer() {
+            let payload = if fragmentation.is_some()
+                && mappings.client.quinnet_channel(quinnet_channel) == Some(Channel::Unreliable)
+            {
+                let Some(payload) = fragments.reassemble(quinnet_channel, payload, time.elapsed())
+                else {
+                    continue;
+                };
+                payload
+            } else {
+                payload
+            };
+            let Some((channel_id, message)) = mappings.client.decode(quinnet_channel, payload)
+            else {
+                warn!("dropping malformed payload from `{client_entity}` on channel {quinnet_channel}");
+                continue;
+            };
             messages.insert_received(client_entity, channel_id, message);
         }
+        if let Some(fragmentation) = &fragmentation {
+            let expired = fragments.discard_expired(time.elapsed(), fragmentation.timeout);
+            if expired > 0 {
+                debug!("dropping {expired} incomplete messages from `{client_entity}`");
+            }
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn send_packets(
+    mut commands: Commands,
     mut quinnet_server: ResMut<QuinnetServer>,
     mut messages: ResMut<ServerMessages>,
-    clients: Query<&NetworkId>,
+    mut send_errors: MessageWriter<TransportSendError>,
+    mut dropped_messages: ResMut<DroppedMessages>,
+    mut diagnostics: Diagnostics,
+    policy: Res<SendErrorPolicy>,
+    mappings: Res<ChannelMappings>,
+    fragmentation: Option<Res<UnreliableFragmentation>>,
+    mut clients: Query<(&NetworkId, &ConnectedClient, &mut FragmentBuffer)>,
 ) {
     let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
         return;
     };
+    let mut dropped = 0;
     for (client_entity, channel_id, message) in messages.drain_sent() {
-        let network_id = clients
-            .get(client_entity)
-            .expect("messages should be sent only to connected clients");
-        endpoint.try_send_payload_on(network_id.get(), channel_id as u8, message);
+        let Ok((network_id, client, mut fragments)) = clients.get_mut(client_entity) else {
+            // Client could have been despawned after replicon wrote its messages.
+            debug!(
+                "dropping message on channel {channel_id} for vanished client `{client_entity}`"
+            );
+            dropped += 1;
+            continue;
+        };
+        let payload_size = message.len();
+        let (quinnet_channel, payload) = mappings.server.encode(channel_id, message);
+        let result = if fragmentation.is_some()
+            && mappings.server.quinnet_channel(quinnet_channel) == Some(Channel::Unreliable)
+        {
+            let max_size =
+                client.max_size + mappings.server.message_header_len() + FRAGMENT_HEADER_LEN;
+            match fragments.split(payload, max_size) {
+                Ok(fragments) => fragments
+                    .into_iter()
+                    .try_for_each(|fragment| {
+                        endpoint.send_payload_on(network_id.get(), quinnet_channel, fragment)
+                    })
+                    .map_err(|err| SendErrorKind::Quinnet(quinnet_send_error(err))),
+                Err(err) => Err(SendErrorKind::Fragmentation(err)),
+            }
+        } else {
+            endpoint
+                .send_payload_on(network_id.get(), quinnet_channel, payload)
+                .map_err(|err| SendErrorKind::Quinnet(quinnet_send_error(err)))
+        };
+        if let Err(err) = result {
+            match *policy {
+                SendErrorPolicy::Ignore => (),
+                SendErrorPolicy::Warn => {
+                    warn!(
+                        "unable to send {payload_size} bytes to `{client_entity}` on channel {channel_id}: {err}"
+                    );
+                }
+                SendErrorPolicy::Disconnect => {
+                    warn!("disconnecting `{client_entity}` after a send failure on channel {channel_id}: {err}");
+                    commands.entity(client_entity).try_despawn();
+                }
+            }
+            send_errors.write(TransportSendError {
+                client: Some(client_entity),
+                channel_id,
+                payload_size,
+                error: err,
+            });
+        }
+    }
+
+    dropped_messages.vanished_clients += dropped;
+    diagnostics.add_measurement(&RepliconQuinnetServerPlugin::DROPPED_MESSAGES, || {
+        dropped as f64
+    });
+}
+
+fn quinnet_send_error(error: EndpointSendError) -> QuinnetSendError {
+    match error {
+        EndpointSendError::UnknownClient(_) => QuinnetSendError::ConnectionClosed,
+        EndpointSendError::ConnectionSendError(error) => error.into(),
     }
 }
 
@@ -166,21 +907,397 @@ fn disconnect_by_request(
 ) {
     for event in disconnect_events.read() {
         debug!("despawning client `{}` by disconnect request", event.client);
-        commands.entity(event.client).despawn();
+        // Could already be despawned by a kick.
+        commands.entity(event.client).try_despawn();
+    }
+}
+
+/// Despawns a client and closes its connection with an application close code and a UTF-8 reason.
+///
+/// Like with [`DisconnectRequest`], messages already written to the client are delivered first.
+/// The client receives [`DisconnectReason::Kicked`] in
+/// [`ServerDisconnected`](crate::client::ServerDisconnected).
+#[derive(Message, Debug, Clone)]
+pub struct KickClient {
+    pub client: Entity,
+    /// Application close code in [`KICK_CODES`].
+    pub code: u32,
+    pub reason: String,
+}
+
+impl KickClient {
+    /// Creates a kick with [`KICKED_CODE`].
+    pub fn new(client: Entity, reason: impl Into<String>) -> Self {
+        Self {
+            client,
+            code: KICKED_CODE,
+            reason: reason.into(),
+        }
+    }
+
+    /// Replaces the application close code.
+    pub fn with_code(mut self, code: u32) -> Self {
+        self.code = code;
+        self
+    }
+}
+
+fn kick_clients(
+    mut commands: Commands,
+    mut kick_events: MessageReader<KickClient>,
+    clients: Query<(), With<ConnectedClient>>,
+) {
+    for kick in kick_events.read() {
+        if !clients.contains(kick.client) {
+            debug!("ignoring kick of `{}` that is not a client", kick.client);
+            continue;
+        }
+        debug!("kicking client `{}`: {}", kick.client, kick.reason);
+        commands
+            .entity(kick.client)
+            .try_insert(kick_reason(kick.code, kick.reason.clone()))
+            .try_despawn();
+    }
+}
+
+/// Returns the reason of a kick or a refusal, replacing codes outside of [`KICK_CODES`].
+fn kick_reason(code: u32, reason: String) -> DisconnectReason {
+    let code = if KICK_CODES.contains(&code) {
+        code
+    } else {
+        warn!("kick code {code} is outside of {KICK_CODES:?}, using {KICKED_CODE} instead");
+        KICKED_CODE
+    };
+    DisconnectReason::Kicked {
+        code: code.into(),
+        reason,
+    }
+}
+
+/// Addresses whose connections are refused with [`BANNED_CODE`].
+///
+/// Only consulted for new connections: ban an address and [kick](KickClient) its clients to
+/// disconnect them too.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct BanList {
+    addresses: HashSet<IpAddr>,
+}
+
+impl BanList {
+    /// Bans an address, returns `false` if it was already banned.
+    pub fn ban(&mut self, addr: IpAddr) -> bool {
+        self.addresses.insert(addr)
+    }
+
+    /// Lifts a ban, returns `false` if the address wasn't banned.
+    pub fn unban(&mut self, addr: IpAddr) -> bool {
+        self.addresses.remove(&addr)
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.addresses.contains(&addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.addresses.iter().copied()
+    }
+}
+
+/// Decides which new connections get a [`ConnectedClient`] entity.
+///
+/// Checked after [`BanList`] when inserted. Refused connections are closed with a
+/// [`DisconnectReason::Kicked`] code before replicon sees them.
+#[derive(Resource, Default)]
+pub struct AdmissionControl {
+    /// Maximum number of connected clients.
+    pub max_clients: Option<usize>,
+    /// Maximum number of connected clients sharing an IP address.
+    pub max_clients_per_ip: Option<usize>,
+    /// When set, only these addresses are admitted.
+    pub allow_list: Option<HashSet<IpAddr>>,
+    filter: Option<Box<AdmissionFilter>>,
+}
+
+/// Custom check run by [`AdmissionControl`] after the built-in limits.
+pub type AdmissionFilter =
+    dyn Fn(&AdmissionRequest) -> Result<(), AdmissionRejection> + Send + Sync + 'static;
+
+impl AdmissionControl {
+    /// Sets a custom check, replacing the previous one.
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&AdmissionRequest) -> Result<(), AdmissionRejection> + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Returns why the connection should be refused, if it should.
+    pub fn check(&self, request: &AdmissionRequest) -> Result<(), AdmissionRejection> {
+        if self
+            .allow_list
+            .as_ref()
+            .is_some_and(|allow_list| !allow_list.contains(&request.remote_addr.ip()))
+        {
+            return Err(AdmissionRejection::new("address not allowed"));
+        }
+        if self
+            .max_clients
+            .is_some_and(|max_clients| request.clients >= max_clients)
+        {
+            return Err(AdmissionRejection::new("server full"));
+        }
+        if self
+            .max_clients_per_ip
+            .is_some_and(|max_clients| request.clients_from_ip >= max_clients)
+        {
+            return Err(AdmissionRejection::new(
+                "too many clients from this address",
+            ));
+        }
+        match &self.filter {
+            Some(filter) => filter(request),
+            None => Ok(()),
+        }
     }
 }
 
+/// A new connection checked by [`AdmissionControl`].
+#[derive(Debug, Clone, Copy)]
+pub struct AdmissionRequest {
+    pub client_id: ClientId,
+    pub remote_addr: SocketAddr,
+    /// Number of connected clients, not counting this one.
+    pub clients: usize,
+    /// Number of connected clients with the same IP address, not counting this one.
+    pub clients_from_ip: usize,
+}
+
+/// Why [`AdmissionControl`] refused a connection, sent to the client as its close code and reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdmissionRejection {
+    /// Application close code in [`KICK_CODES`].
+    pub code: u32,
+    pub reason: String,
+}
+
+impl AdmissionRejection {
+    /// Creates a rejection with [`REJECTED_CODE`].
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            code: REJECTED_CODE,
+            reason: reason.into(),
+        }
+    }
+
+    /// Replaces the application close code.
+    pub fn with_code(mut self, code: u32) -> Self {
+        self.code = code;
+        self
+    }
+}
+
+/// Written when a client entity is despawned, see [`DisconnectReason`].
+#[derive(Message, Debug, Clone)]
+pub struct ClientDisconnected {
+    pub client: Entity,
+    pub reason: DisconnectReason,
+}
+
 fn disconnect_client(
     remove: On<Remove, ConnectedClient>,
+    mut pending: ResMut<PendingDisconnects>,
+    mut disconnects: MessageWriter<ClientDisconnected>,
+    clients: Query<(&NetworkId, Option<&DisconnectReason>)>,
+    quinnet_server: Res<QuinnetServer>,
+    shutting_down: Option<Res<ShuttingDown>>,
+) {
+    let (network_id, reason) = clients.get(remove.entity).expect("inserted on connection");
+    let reason = reason.cloned().unwrap_or(DisconnectReason::Local);
+    debug!(
+        "disconnecting despawned client `{}`: {reason:?}",
+        remove.entity
+    );
+    disconnects.write(ClientDisconnected {
+        client: remove.entity,
+        reason: reason.clone(),
+    });
+
+    // Client ids restart with each endpoint, so the connection must be closed by this one.
+    if quinnet_server.get_endpoint().is_none() {
+        return;
+    }
+    let remaining = shutting_down.map_or(pending.grace_period, |shutting_down| {
+        shutting_down.remaining
+    });
+    pending.disconnects.push(PendingDisconnect {
+        client_id: network_id.get(),
+        reason,
+        remaining,
+        transmitted_bytes: 0,
+        quiet: Duration::ZERO,
+    });
+}
+
+/// Connections of despawned clients that are kept open until their pending data is delivered.
+#[derive(Resource)]
+struct PendingDisconnects {
+    grace_period: Duration,
+    disconnects: Vec<PendingDisconnect>,
+}
+
+impl PendingDisconnects {
+    fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            disconnects: Default::default(),
+        }
+    }
+}
+
+struct PendingDisconnect {
+    client_id: ClientId,
+    reason: DisconnectReason,
+    remaining: Duration,
+    transmitted_bytes: u64,
+    quiet: Duration,
+}
+
+/// Closes pending connections once nothing was transmitted for 3 RTTs or their grace period expired.
+///
+/// Neither Quinnet nor Quinn expose acknowledgements of stream data, so an idle connection is
+/// assumed to be delivered. This is a heuristic, see
+/// [`RepliconQuinnetServerPlugin::disconnect_grace_period`].
+fn drain_disconnects(
+    mut pending: ResMut<PendingDisconnects>,
     mut quinnet_server: ResMut<QuinnetServer>,
-    clients: Query<&NetworkId>,
+    time: Res<Time>,
 ) {
     let Some(endpoint) = quinnet_server.get_endpoint_mut() else {
         return;
     };
+    pending.disconnects.retain_mut(|disconnect| {
+        let Some(con) = endpoint.connection(disconnect.client_id) else {
+            // Already closed by the peer.
+            return false;
+        };
+        let quinn_stats = con.quinn_connection_stats();
+        if disconnect.transmitted_bytes != quinn_stats.udp_tx.bytes {
+            disconnect.transmitted_bytes = quinn_stats.udp_tx.bytes;
+            disconnect.quiet = Duration::ZERO;
+        } else {
+            disconnect.quiet += time.delta();
+        }
+        disconnect.remaining = disconnect.remaining.saturating_sub(time.delta());
 
-    debug!("disconnecting despawned client `{}`", remove.entity);
+        let drained = disconnect.quiet >= 3 * quinn_stats.path.rtt;
+        if !drained && !disconnect.remaining.is_zero() {
+            return true;
+        }
+        if !drained {
+            warn!(
+                "closing connection {} with pending data after the grace period",
+                disconnect.client_id
+            );
+        }
+        close_connection(endpoint, disconnect.client_id, &disconnect.reason);
+        false
+    });
+}
 
-    let network_id = clients.get(remove.entity).expect("inserted on connection");
-    endpoint.try_disconnect_client(network_id.get());
+/// Closes a client connection, with an application close code for reasons defined by this crate.
+fn close_connection(endpoint: &mut Endpoint, client_id: ClientId, reason: &DisconnectReason) {
+    match reason {
+        DisconnectReason::ServerShutdown => endpoint.try_disconnect_client_with_code(
+            client_id,
+            VarInt::from_u32(SERVER_SHUTDOWN_CODE),
+            &[],
+        ),
+        DisconnectReason::Kicked { code, reason } => endpoint.try_disconnect_client_with_code(
+            client_id,
+            VarInt::from_u64(*code).unwrap_or(VarInt::from_u32(KICKED_CODE)),
+            reason.as_bytes(),
+        ),
+        _ => endpoint.try_disconnect_client(client_id),
+    }
+}
+
+/// Gracefully stops the server.
+///
+/// Replicon messages already written are still sent. Clients are despawned with
+/// [`DisconnectReason::ServerShutdown`] and their connections are closed with
+/// [`SERVER_SHUTDOWN_CODE`] once nothing was transmitted to them for 3 RTTs, or after `timeout`.
+/// Then the endpoint is stopped. New connections are refused in the meantime.
+///
+/// Like for [`RepliconQuinnetServerPlugin::disconnect_grace_period`], delivery is a best effort:
+/// messages may still be lost on a lossy link before the timeout.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct ShutdownServer {
+    pub timeout: Duration,
+}
+
+impl Default for ShutdownServer {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Present while the server is shutting down, see [`ShutdownServer`].
+#[derive(Resource, Debug)]
+pub struct ShuttingDown {
+    remaining: Duration,
+}
+
+impl ShuttingDown {
+    /// Returns the time left before clients are disconnected regardless of pending data.
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+}
+
+fn start_shutdown(
+    mut commands: Commands,
+    mut shutdown_events: MessageReader<ShutdownServer>,
+    shutting_down: Option<Res<ShuttingDown>>,
+    clients: Query<Entity, With<ConnectedClient>>,
+) {
+    let Some(shutdown) = shutdown_events.read().last() else {
+        return;
+    };
+    if shutting_down.is_some() {
+        return;
+    }
+
+    debug!("shutting down the server");
+    commands.insert_resource(ShuttingDown {
+        remaining: shutdown.timeout,
+    });
+    for client_entity in &clients {
+        commands
+            .entity(client_entity)
+            .insert(DisconnectReason::ServerShutdown)
+            .despawn();
+    }
+}
+
+/// Stops the endpoint on the update after all client connections were closed.
+fn drain_shutdown(
+    mut commands: Commands,
+    mut shutting_down: ResMut<ShuttingDown>,
+    mut quinnet_server: ResMut<QuinnetServer>,
+    pending: Res<PendingDisconnects>,
+    time: Res<Time>,
+) {
+    shutting_down.remaining = shutting_down.remaining.saturating_sub(time.delta());
+    if !pending.disconnects.is_empty() {
+        return;
+    }
+
+    debug!("stopping the endpoint");
+    if let Err(err) = quinnet_server.stop_endpoint() {
+        warn!("unable to stop the endpoint: {err}");
+    }
+    commands.remove_resource::<ShuttingDown>();
 }
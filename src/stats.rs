@@ -0,0 +1,260 @@
+//! Windowed statistics shared by the client and server backends.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::ecs::{component::Component, resource::Resource};
+use quinn_proto::{ConnectionStats, FrameStats, UdpStats};
+
+use crate::BYTES_PER_SEC_PERIOD;
+
+/// Number of [`BYTES_PER_SEC_PERIOD`] samples kept by a [`BandwidthWindow`].
+pub const BANDWIDTH_WINDOW_SAMPLES: usize = 10;
+
+/// Sliding window of transferred bytes, used to compute throughput for a single connection.
+///
+/// Bytes are sampled every [`BYTES_PER_SEC_PERIOD`] and averaged over the last
+/// [`BANDWIDTH_WINDOW_SAMPLES`] samples.
+#[derive(Component, Debug, Default, Clone)]
+pub struct BandwidthWindow {
+    timer: f64,
+    samples: VecDeque<BandwidthSample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BandwidthSample {
+    duration: f64,
+    sent_bytes: u64,
+    received_bytes: u64,
+}
+
+impl BandwidthWindow {
+    /// Advances the window timer by `delta` seconds.
+    ///
+    /// Returns `true` when a new sample is due and should be pushed with [`Self::push_sample`].
+    pub fn tick(&mut self, delta: f64) -> bool {
+        self.timer += delta;
+        self.timer >= BYTES_PER_SEC_PERIOD
+    }
+
+    /// Records the bytes transferred since the last sample and resets the timer.
+    pub fn push_sample(&mut self, sent_bytes: u64, received_bytes: u64) {
+        if self.samples.len() == BANDWIDTH_WINDOW_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(BandwidthSample {
+            duration: self.timer,
+            sent_bytes,
+            received_bytes,
+        });
+        self.timer = 0.;
+    }
+
+    /// Average sent bytes per second over the window.
+    pub fn sent_bps(&self) -> f64 {
+        self.bps(|sample| sample.sent_bytes)
+    }
+
+    /// Average received bytes per second over the window.
+    pub fn received_bps(&self) -> f64 {
+        self.bps(|sample| sample.received_bytes)
+    }
+
+    fn bps(&self, bytes: impl Fn(&BandwidthSample) -> u64) -> f64 {
+        let duration: f64 = self.samples.iter().map(|sample| sample.duration).sum();
+        if duration <= 0. {
+            return 0.;
+        }
+        let bytes: u64 = self.samples.iter().map(bytes).sum();
+        bytes as f64 / duration
+    }
+}
+
+/// Number of [`BYTES_PER_SEC_PERIOD`] samples kept by a [`PathStatsWindow`].
+pub const PATH_WINDOW_SAMPLES: usize = 10;
+
+/// Sliding window over Quinn path statistics, used to compute recent packet loss and RTT jitter.
+///
+/// Quinn only exposes lifetime packet counters, so the window stores their deltas between samples,
+/// taken every [`BYTES_PER_SEC_PERIOD`] and kept for the last [`PATH_WINDOW_SAMPLES`] samples.
+#[derive(Component, Resource, Debug, Default, Clone)]
+pub struct PathStatsWindow {
+    timer: f64,
+    last_sent_packets: u64,
+    last_lost_packets: u64,
+    samples: VecDeque<PathSample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PathSample {
+    sent_packets: u64,
+    lost_packets: u64,
+    rtt: f64,
+}
+
+impl PathStatsWindow {
+    /// Advances the window timer by `delta` seconds.
+    ///
+    /// Returns `true` when a new sample is due and should be pushed with [`Self::push_sample`].
+    pub fn tick(&mut self, delta: f64) -> bool {
+        self.timer += delta;
+        self.timer >= BYTES_PER_SEC_PERIOD
+    }
+
+    /// Records the lifetime path counters of the connection and resets the timer.
+    pub fn push_sample(&mut self, sent_packets: u64, lost_packets: u64, rtt: Duration) {
+        // Counters going backward means that they belong to a new connection.
+        if sent_packets < self.last_sent_packets || lost_packets < self.last_lost_packets {
+            self.last_sent_packets = 0;
+            self.last_lost_packets = 0;
+        }
+        if self.samples.len() == PATH_WINDOW_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(PathSample {
+            sent_packets: sent_packets - self.last_sent_packets,
+            lost_packets: lost_packets - self.last_lost_packets,
+            rtt: rtt.as_secs_f64(),
+        });
+        self.last_sent_packets = sent_packets;
+        self.last_lost_packets = lost_packets;
+        self.timer = 0.;
+    }
+
+    /// Percentage of packets lost over the window, `0` if nothing was sent.
+    pub fn packet_loss(&self) -> f64 {
+        let sent_packets: u64 = self.samples.iter().map(|sample| sample.sent_packets).sum();
+        if sent_packets == 0 {
+            return 0.;
+        }
+        let lost_packets: u64 = self.samples.iter().map(|sample| sample.lost_packets).sum();
+        100. * lost_packets as f64 / sent_packets as f64
+    }
+
+    /// Mean absolute difference between consecutive RTT samples over the window, in seconds.
+    pub fn jitter(&self) -> f64 {
+        if self.samples.len() < 2 {
+            return 0.;
+        }
+        let total: f64 = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(previous, next)| (next.rtt - previous.rtt).abs())
+            .sum();
+        total / (self.samples.len() - 1) as f64
+    }
+}
+
+/// QUIC diagnostics of a connection, mirrored from Quinn's connection statistics every update.
+///
+/// Optional on both sides:
+/// - On the server, insert it on client entities, for example with
+///   `app.register_required_components::<ConnectedClient, QuinnetConnectionDiagnostics>()`.
+/// - On the client, insert it as a resource.
+#[derive(Component, Resource, Debug, Default, Clone, Copy)]
+pub struct QuinnetConnectionDiagnostics {
+    /// Current best estimate of the round-trip time.
+    pub rtt: Duration,
+    /// Current congestion window, in bytes.
+    pub cwnd: u64,
+    /// Number of congestion events.
+    pub congestion_events: u64,
+    /// Number of lost packets.
+    pub lost_packets: u64,
+    /// Number of lost bytes.
+    pub lost_bytes: u64,
+    /// Number of sent packets.
+    pub sent_packets: u64,
+    /// Number of sent path MTU discovery probes.
+    pub sent_plpmtud_probes: u64,
+    /// Number of lost path MTU discovery probes.
+    pub lost_plpmtud_probes: u64,
+    /// Number of times a black hole was detected on the path.
+    pub black_holes_detected: u64,
+    /// Largest UDP payload size currently supported by the path.
+    pub current_mtu: u16,
+    /// Transmitted UDP datagrams.
+    pub udp_tx: UdpDiagnostics,
+    /// Received UDP datagrams.
+    pub udp_rx: UdpDiagnostics,
+    /// Transmitted QUIC frames.
+    pub frame_tx: FrameDiagnostics,
+    /// Received QUIC frames.
+    pub frame_rx: FrameDiagnostics,
+}
+
+/// UDP datagrams counters of a [`QuinnetConnectionDiagnostics`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UdpDiagnostics {
+    pub datagrams: u64,
+    pub bytes: u64,
+    /// Number of I/O operations, can be less than `datagrams` with batched system calls.
+    pub ios: u64,
+}
+
+/// QUIC frames counters of a [`QuinnetConnectionDiagnostics`], by frame type.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameDiagnostics {
+    pub acks: u64,
+    pub crypto: u64,
+    pub connection_close: u64,
+    pub data_blocked: u64,
+    pub datagram: u64,
+    pub max_data: u64,
+    pub max_stream_data: u64,
+    pub ping: u64,
+    pub reset_stream: u64,
+    pub stop_sending: u64,
+    pub stream: u64,
+    pub stream_data_blocked: u64,
+}
+
+impl From<&ConnectionStats> for QuinnetConnectionDiagnostics {
+    fn from(stats: &ConnectionStats) -> Self {
+        Self {
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            congestion_events: stats.path.congestion_events,
+            lost_packets: stats.path.lost_packets,
+            lost_bytes: stats.path.lost_bytes,
+            sent_packets: stats.path.sent_packets,
+            sent_plpmtud_probes: stats.path.sent_plpmtud_probes,
+            lost_plpmtud_probes: stats.path.lost_plpmtud_probes,
+            black_holes_detected: stats.path.black_holes_detected,
+            current_mtu: stats.path.current_mtu,
+            udp_tx: (&stats.udp_tx).into(),
+            udp_rx: (&stats.udp_rx).into(),
+            frame_tx: (&stats.frame_tx).into(),
+            frame_rx: (&stats.frame_rx).into(),
+        }
+    }
+}
+
+impl From<&UdpStats> for UdpDiagnostics {
+    fn from(stats: &UdpStats) -> Self {
+        Self {
+            datagrams: stats.datagrams,
+            bytes: stats.bytes,
+            ios: stats.ios,
+        }
+    }
+}
+
+impl From<&FrameStats> for FrameDiagnostics {
+    fn from(stats: &FrameStats) -> Self {
+        Self {
+            acks: stats.acks,
+            crypto: stats.crypto,
+            connection_close: stats.connection_close,
+            data_blocked: stats.data_blocked,
+            datagram: stats.datagram,
+            max_data: stats.max_data,
+            max_stream_data: stats.max_stream_data,
+            ping: stats.ping,
+            reset_stream: stats.reset_stream,
+            stop_sending: stats.stop_sending,
+            stream: stats.stream,
+            stream_data_blocked: stats.stream_data_blocked,
+        }
+    }
+}
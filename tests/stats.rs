@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use bevy_replicon_quinnet::{
+    stats::{PathStatsWindow, PATH_WINDOW_SAMPLES},
+    BYTES_PER_SEC_PERIOD,
+};
+
+#[test]
+fn packet_loss_window() {
+    let mut window = PathStatsWindow::default();
+    assert_eq!(window.packet_loss(), 0.0, "nothing sent yet");
+
+    window.push_sample(100, 50, Duration::ZERO);
+    assert_eq!(window.packet_loss(), 50.0);
+
+    let mut sent_packets = 100;
+    for _ in 0..PATH_WINDOW_SAMPLES {
+        sent_packets += 100;
+        window.push_sample(sent_packets, 50, Duration::ZERO);
+    }
+    assert_eq!(
+        window.packet_loss(),
+        0.0,
+        "loss spike should leave the window"
+    );
+}
+
+#[test]
+fn counter_reset() {
+    let mut window = PathStatsWindow::default();
+    window.push_sample(1000, 0, Duration::ZERO);
+
+    // Counters of a new connection.
+    window.push_sample(10, 5, Duration::ZERO);
+    assert_eq!(window.packet_loss(), 100.0 * 5.0 / 1010.0);
+}
+
+#[test]
+fn jitter() {
+    let mut window = PathStatsWindow::default();
+    window.push_sample(0, 0, Duration::from_millis(100));
+    assert_eq!(window.jitter(), 0.0, "needs at least two samples");
+
+    window.push_sample(0, 0, Duration::from_millis(120));
+    window.push_sample(0, 0, Duration::from_millis(80));
+    assert!((window.jitter() - 0.03).abs() < 1e-9);
+
+    for _ in 0..PATH_WINDOW_SAMPLES {
+        window.push_sample(0, 0, Duration::from_millis(50));
+    }
+    assert_eq!(window.jitter(), 0.0, "old samples should leave the window");
+}
+
+#[test]
+fn tick() {
+    let mut window = PathStatsWindow::default();
+    assert!(!window.tick(BYTES_PER_SEC_PERIOD / 2.0));
+    assert!(window.tick(BYTES_PER_SEC_PERIOD / 2.0));
+
+    window.push_sample(0, 0, Duration::ZERO);
+    assert!(
+        !window.tick(BYTES_PER_SEC_PERIOD / 2.0),
+        "pushing a sample should reset the timer"
+    );
+}
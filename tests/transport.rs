@@ -5,24 +5,1308 @@ use std::{
 };
 
 use bevy::prelude::*;
-use bevy::{ecs::schedule::ScheduleLabel, state::app::StatesPlugin};
+use bevy::{diagnostic::DiagnosticsStore, ecs::schedule::ScheduleLabel, state::app::StatesPlugin};
 use bevy_quinnet::{
     client::{
-        certificate::CertificateVerificationMode, connection::ClientAddrConfiguration,
+        certificate::CertificateVerificationMode,
+        connection::{ClientAddrConfiguration, ConnectionLocalId},
         ClientConnectionConfiguration, ClientConnectionConfigurationDefaultables, QuinnetClient,
     },
     server::{
         certificate::CertificateRetrievalMode, EndpointAddrConfiguration, QuinnetServer,
         ServerEndpointConfiguration, ServerEndpointConfigurationDefaultables,
     },
+    shared::{channels::ChannelConfig, error::ConnectionSendError},
 };
-use bevy_replicon::prelude::*;
-use bevy_replicon_quinnet::{ChannelsConfigurationExt, RepliconQuinnetPlugins};
+use bevy_replicon::{prelude::*, shared::backend::connected_client::NetworkId};
+use bevy_replicon_quinnet::{
+    channels::{
+        ChannelConfigError, ChannelMapping, ChannelMappings, ChannelOverride,
+        QuinnetChannelOverrides, MAX_QUINNET_CHANNELS, MAX_RELIABLE_FRAME_SIZE,
+        MAX_REPLICON_CHANNELS, QUINNET_DATAGRAM_HEADER_LEN,
+    },
+    client::{
+        ActiveConnection, ClientAuth, HandoffCompleted, MaxDatagramSize, PendingHandoff,
+        ReconnectPolicy, ReconnectProgress, Reconnecting, RepliconQuinnetClientCommandsExt,
+        RepliconQuinnetClientPlugin, ServerDisconnected,
+    },
+    fragmentation::UnreliableFragmentation,
+    server::{
+        AdmissionControl, AdmissionRejection, BanList, ClientDisconnected, DroppedMessages,
+        KickClient, RepliconQuinnetServerConfig, RepliconQuinnetServerPlugin, ServerAuth,
+        ServerStartFailed, ShutdownServer, ShuttingDown, StaleConnection,
+    },
+    stats::{BandwidthWindow, PathStatsWindow, QuinnetConnectionDiagnostics},
+    ChannelsConfigurationExt, DisconnectReason, QuinnetSendError, RepliconQuinnetPlugins,
+    SendErrorKind, SendErrorPolicy, TransportSendError, BANNED_CODE, KICKED_CODE, KICK_CODES,
+    REJECTED_CODE, SERVER_SHUTDOWN_CODE,
+};
+use bytes::Bytes;
+use quinn_proto::{ApplicationClose, ConnectionError, VarInt};
 use serde::{Deserialize, Serialize};
 
 #[test]
-fn connect_disconnect() {
-    let port = 6000; // TODO Use port 0 and retrieve the port used by the server.
+fn connect_disconnect() {
+    let port = 6000; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Running);
+
+    let quinnet_server = server_app.world().resource::<QuinnetServer>();
+    assert_eq!(quinnet_server.endpoint().clients().len(), 1);
+
+    // // TODO Better way to wait a bit more for `AuthorizedClient`component insertion. Maybe wait on `ProtocolHash` event ?
+    sleep(Duration::from_secs_f32(0.05));
+    server_app.update();
+
+    let mut clients = server_app
+        .world_mut()
+        .query::<(&ConnectedClient, &AuthorizedClient)>();
+    assert_eq!(clients.iter(server_app.world()).len(), 1);
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Connected);
+
+    let mut quinnet_client = client_app.world_mut().resource_mut::<QuinnetClient>();
+    assert!(quinnet_client.is_connected());
+
+    let default_connection = quinnet_client.get_default_connection().unwrap();
+    quinnet_client.close_connection(default_connection).unwrap();
+
+    client_app.update();
+
+    server_wait_for_disconnect(&mut server_app);
+
+    assert_eq!(clients.iter(server_app.world()).len(), 0);
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Disconnected);
+
+    let mut quinnet_server = server_app.world_mut().resource_mut::<QuinnetServer>();
+    assert_eq!(quinnet_server.endpoint().clients().len(), 0);
+
+    quinnet_server.stop_endpoint().unwrap();
+
+    server_app.update();
+
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(
+        *server_state,
+        ServerState::Stopped,
+        "requires resource removal"
+    );
+}
+
+#[test]
+fn disconnect_request() {
+    let port = 6001; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_server_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    server_app.world_mut().spawn(Replicated);
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+    server_app
+        .world_mut()
+        .write_message(DisconnectRequest { client });
+
+    server_app.update();
+
+    assert_eq!(clients.iter(server_app.world()).len(), 0);
+
+    let mut received = 0;
+    while *client_app.world().resource::<State<ClientState>>() != ClientState::Disconnected {
+        sleep(Duration::from_secs_f32(0.01));
+        server_app.update();
+        client_app.update();
+        let messages = client_app.world().resource::<Messages<Test>>();
+        received += messages.iter_current_update_messages().count();
+    }
+    assert_eq!(received, 1, "last message should be received");
+
+    let mut replicated = client_app.world_mut().query::<&Replicated>();
+    assert_eq!(
+        replicated.iter(client_app.world()).len(),
+        1,
+        "last replication should be received"
+    );
+}
+
+#[test]
+fn replication() {
+    let port = 6002; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    server_app.world_mut().spawn(Replicated);
+
+    server_app.update();
+    client_wait_for_message(&mut client_app);
+
+    let mut replicated = client_app.world_mut().query::<&Replicated>();
+    assert_eq!(replicated.iter(client_app.world()).len(), 1);
+}
+
+#[test]
+fn server_event() {
+    let port = 6003; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_server_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
+
+    server_app.update();
+    client_wait_for_message(&mut client_app);
+
+    let messages = client_app.world().resource::<Messages<Test>>();
+    assert_eq!(messages.len(), 1);
+}
+
+#[test]
+fn client_event() {
+    let port = 6004; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    let client_id = setup(&mut server_app, &mut client_app, port);
+
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Running);
+
+    client_app.world_mut().write_message(Test);
+
+    client_app.update();
+    server_wait_for_message(&mut server_app, client_id);
+
+    let client_messages = server_app.world().resource::<Messages<FromClient<Test>>>();
+    assert_eq!(client_messages.len(), 1);
+}
+
+#[test]
+fn server_config() {
+    let port = 6005; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut conflicting_app = App::new();
+    for app in [&mut server_app, &mut conflicting_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+        app.insert_resource(RepliconQuinnetServerConfig::new(
+            EndpointAddrConfiguration::from_ip(IpAddr::V6(Ipv6Addr::LOCALHOST), port),
+            CertificateRetrievalMode::GenerateSelfSigned {
+                server_hostname: Ipv6Addr::LOCALHOST.to_string(),
+            },
+        ));
+    }
+
+    server_app.update();
+    server_app.update();
+
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Running);
+
+    conflicting_app.update();
+
+    let start_errors = conflicting_app
+        .world()
+        .resource::<Messages<ServerStartFailed>>();
+    assert_eq!(start_errors.len(), 1, "port should already be in use");
+}
+
+#[test]
+fn connect_replicon() {
+    let port = 6006; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup_server(&mut server_app, port);
+    client_app.world_mut().commands().connect_replicon(
+        ClientAddrConfiguration::from_ips(
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            port,
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            0,
+        ),
+        CertificateVerificationMode::SkipVerification,
+    );
+    client_app.world_mut().flush();
+    let client_id = wait_for_connection(&mut server_app, &mut client_app);
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Connected);
+
+    client_app.world_mut().write_message(Test);
+
+    client_app.update();
+    server_wait_for_message(&mut server_app, client_id);
+
+    let client_messages = server_app.world().resource::<Messages<FromClient<Test>>>();
+    assert_eq!(client_messages.len(), 1);
+}
+
+#[test]
+fn per_client_statistics() {
+    let port = 6007; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_apps = [App::new(), App::new()];
+    for app in std::iter::once(&mut server_app).chain(&mut client_apps) {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_server_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup_server(&mut server_app, port);
+    for client_app in &mut client_apps {
+        setup_client(client_app, port);
+        wait_for_connection(&mut server_app, client_app);
+    }
+
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
+    server_app.update();
+
+    // Wait for a whole bytes per second period.
+    sleep(Duration::from_secs_f32(0.15));
+    server_app.update();
+
+    let mut clients = server_app.world_mut().query::<&ClientStats>();
+    let stats: Vec<_> = clients.iter(server_app.world()).collect();
+    assert_eq!(stats.len(), 2);
+    for client_stats in stats {
+        assert!(
+            client_stats.sent_bps > 0.,
+            "throughput should be updated for every client"
+        );
+        assert!(!client_stats.packet_loss.is_nan());
+    }
+}
+
+#[test]
+fn stale_connection() {
+    let port = 6008; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+
+    let stale_client = server_app
+        .world_mut()
+        .spawn((
+            ConnectedClient { max_size: 1200 },
+            NetworkId::new(u64::MAX),
+            ClientStats::default(),
+        ))
+        .id();
+    let stale_entity = server_app.world().entity(stale_client);
+    assert!(
+        stale_entity.contains::<BandwidthWindow>() && stale_entity.contains::<PathStatsWindow>(),
+        "stale client should be iterated by the statistics update"
+    );
+
+    // Move the real client into a newer archetype to iterate it after the stale one.
+    server_app
+        .world_mut()
+        .entity_mut(client)
+        .insert((ClientStats::default(), Moved));
+
+    server_app.update();
+
+    assert!(server_app
+        .world()
+        .get::<StaleConnection>(stale_client)
+        .is_some());
+    let client_stats = server_app.world().get::<ClientStats>(client).unwrap();
+    assert!(
+        client_stats.rtt > 0.,
+        "statistics should be updated despite the stale client"
+    );
+
+    server_app.update();
+
+    assert!(
+        server_app.world().get_entity(stale_client).is_err(),
+        "stale client should be despawned"
+    );
+}
+
+#[test]
+fn connection_diagnostics() {
+    let port = 6009; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+    server_app.register_required_components::<ConnectedClient, QuinnetConnectionDiagnostics>();
+    client_app.init_resource::<QuinnetConnectionDiagnostics>();
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let mut clients = server_app
+        .world_mut()
+        .query::<&QuinnetConnectionDiagnostics>();
+    let diagnostics = clients.single(server_app.world()).unwrap();
+    assert!(diagnostics.sent_packets > 0);
+    assert!(diagnostics.cwnd > 0);
+
+    let diagnostics = client_app
+        .world()
+        .resource::<QuinnetConnectionDiagnostics>();
+    assert!(diagnostics.sent_packets > 0);
+    assert!(diagnostics.cwnd > 0);
+}
+
+#[test]
+fn max_size() {
+    let port = 6013; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins.set(RepliconQuinnetServerPlugin {
+                initial_max_size: 1000,
+                ..Default::default()
+            }),
+        ))
+        .finish();
+    }
+
+    setup_server(&mut server_app, port);
+    setup_client(&mut client_app, port);
+
+    // Check the first frame the client exists, before the statistics update can correct it.
+    let mut clients = server_app
+        .world_mut()
+        .query::<(&ConnectedClient, &NetworkId)>();
+    let (max_size, client_id) = loop {
+        client_app.update();
+        server_app.update();
+        if let Ok((client, network_id)) = clients.single(server_app.world()) {
+            break (client.max_size, network_id.get());
+        }
+    };
+    let datagram_size = server_app
+        .world_mut()
+        .resource_mut::<QuinnetServer>()
+        .get_endpoint_mut()
+        .unwrap()
+        .connection_mut(client_id)
+        .unwrap()
+        .max_datagram_size()
+        .unwrap();
+    assert_eq!(
+        max_size,
+        datagram_size - QUINNET_DATAGRAM_HEADER_LEN,
+        "max size should be taken from the connection without Quinnet's header instead of the initial value"
+    );
+}
+
+#[test]
+fn oversize_unreliable() {
+    let port = 6014; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Blob>(Channel::Unreliable)
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let max_size = client_app.world().resource::<MaxDatagramSize>().0;
+    client_app
+        .world_mut()
+        .write_message(Blob(vec![0; max_size + 1]));
+    client_app.update();
+
+    let send_errors = client_app
+        .world()
+        .resource::<Messages<TransportSendError>>();
+    assert_eq!(send_errors.len(), 1);
+    let send_error = send_errors.iter_current_update_messages().next().unwrap();
+    assert!(matches!(send_error.error, SendErrorKind::Oversize { .. }));
+
+    let store = client_app.world().resource::<DiagnosticsStore>();
+    let oversize = store
+        .get_measurement(&RepliconQuinnetClientPlugin::OVERSIZE_MESSAGES)
+        .unwrap();
+    assert_eq!(oversize.value, 1.0);
+}
+
+#[test]
+fn send_error_disconnect() {
+    let port = 6027; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Blob>(Channel::Unreliable)
+        .insert_resource(SendErrorPolicy::Disconnect)
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let max_size = client_app.world().resource::<MaxDatagramSize>().0;
+    client_app
+        .world_mut()
+        .write_message(Blob(vec![0; max_size + 1]));
+    client_app.update();
+
+    let send_errors = client_app
+        .world()
+        .resource::<Messages<TransportSendError>>();
+    let send_error = send_errors.iter_current_update_messages().next().unwrap();
+    assert_eq!(send_error.client, None);
+    assert!(matches!(send_error.error, SendErrorKind::Oversize { .. }));
+
+    let reason = client_wait_for_disconnect(&mut server_app, &mut client_app);
+    assert_eq!(
+        reason,
+        DisconnectReason::Local,
+        "the client should close its connection after the send failure"
+    );
+}
+
+#[test]
+fn fragmentation() {
+    let port = 6015; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Blob>(Channel::Unreliable)
+        .add_server_message::<Blob>(Channel::Unreliable)
+        .init_resource::<UnreliableFragmentation>()
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let size = 3 * client_app.world().resource::<MaxDatagramSize>().0;
+    client_app.world_mut().write_message(Blob(vec![1; size]));
+    client_app.update();
+
+    loop {
+        sleep(Duration::from_secs_f32(0.05));
+        server_app.update();
+        let client_messages = server_app.world().resource::<Messages<FromClient<Blob>>>();
+        if let Some(message) = client_messages.iter_current_update_messages().next() {
+            assert_eq!(message.message.0, vec![1; size]);
+            break;
+        }
+    }
+
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Blob(vec![2; size]),
+    });
+    server_app.update();
+
+    loop {
+        sleep(Duration::from_secs_f32(0.05));
+        client_app.update();
+        let messages = client_app.world().resource::<Messages<Blob>>();
+        if let Some(message) = messages.iter_current_update_messages().next() {
+            assert_eq!(message.0, vec![2; size]);
+            break;
+        }
+    }
+}
+
+#[test]
+fn fragmentation_max_size() {
+    let port = 6029; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .init_resource::<UnreliableFragmentation>();
+    }
+    let mut server_channels = server_app.world_mut().resource_mut::<RepliconChannels>();
+    let channel_id = server_channels.create_server_channel(Channel::Unreliable);
+    let mut client_channels = client_app.world_mut().resource_mut::<RepliconChannels>();
+    client_channels.create_server_channel(Channel::Unreliable);
+    for app in [&mut server_app, &mut client_app] {
+        app.finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+    server_app
+        .world_mut()
+        .entity_mut(client)
+        .insert(QuinnetConnectionDiagnostics::default());
+    server_app.update();
+
+    let sent_datagrams = |server_app: &App| {
+        server_app
+            .world()
+            .get::<QuinnetConnectionDiagnostics>(client)
+            .unwrap()
+            .frame_tx
+            .datagram
+    };
+    let datagrams_before = sent_datagrams(&server_app);
+    let max_size = server_app
+        .world()
+        .get::<ConnectedClient>(client)
+        .unwrap()
+        .max_size;
+    server_app
+        .world_mut()
+        .resource_mut::<ServerMessages>()
+        .send(client, channel_id, vec![0; max_size]);
+    server_app.update();
+
+    loop {
+        sleep(Duration::from_secs_f32(0.05));
+        client_app.update();
+        let mut messages = client_app.world_mut().resource_mut::<ClientMessages>();
+        if let Some(message) = messages.receive(channel_id).next() {
+            assert_eq!(message.len(), max_size);
+            break;
+        }
+    }
+
+    server_app.update();
+    assert_eq!(
+        sent_datagrams(&server_app) - datagrams_before,
+        1,
+        "a message of `max_size` should fit in a single datagram"
+    );
+}
+
+#[test]
+fn active_connection() {
+    let port = 6016; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup_server(&mut server_app, port);
+    let lobby = setup_client(&mut client_app, port);
+    let game = setup_client(&mut client_app, port);
+    client_app
+        .world_mut()
+        .insert_resource(ActiveConnection::new(game));
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<(), With<ConnectedClient>>();
+    loop {
+        client_app.update();
+        server_app.update();
+        let quinnet_client = client_app.world().resource::<QuinnetClient>();
+        if [lobby, game].iter().all(|&connection_id| {
+            quinnet_client
+                .get_connection_by_id(connection_id)
+                .unwrap()
+                .is_connected()
+        }) && clients.iter(server_app.world()).len() == 2
+        {
+            break;
+        }
+    }
+    client_app.update();
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Connected);
+
+    client_app.world_mut().write_message(Test);
+    client_app.update();
+
+    let quinnet_client = client_app.world().resource::<QuinnetClient>();
+    let client_id = |connection_id| {
+        quinnet_client
+            .get_connection_by_id(connection_id)
+            .unwrap()
+            .client_id()
+            .unwrap()
+    };
+    let (lobby_id, game_id) = (client_id(lobby), client_id(game));
+    server_wait_for_message(&mut server_app, game_id);
+    let endpoint = server_app.world().resource::<QuinnetServer>().endpoint();
+    let received_count = |client_id| {
+        endpoint
+            .connection(client_id)
+            .unwrap()
+            .stats()
+            .received_messages_count()
+    };
+    assert_eq!(
+        received_count(lobby_id),
+        0,
+        "messages should use the active connection"
+    );
+
+    client_app
+        .world_mut()
+        .resource_mut::<ActiveConnection>()
+        .set(Some(lobby));
+    client_app.update();
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(
+        *client_state,
+        ClientState::Disconnected,
+        "switching should reset replicon"
+    );
+
+    client_app.update();
+
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Connected);
+    let quinnet_client = client_app.world().resource::<QuinnetClient>();
+    assert!(
+        quinnet_client
+            .get_connection_by_id(game)
+            .unwrap()
+            .is_connected(),
+        "switching shouldn't close the previous connection"
+    );
+}
+
+#[test]
+fn handoff() {
+    let source_port = 6017; // TODO Use port 0 and retrieve the port used by the server.
+    let target_port = 6018;
+    let mut source_app = App::new();
+    let mut target_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut source_app, &mut target_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_client_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup(&mut source_app, &mut client_app, source_port);
+    setup_server(&mut target_app, target_port);
+
+    client_app.world_mut().commands().handoff_replicon(
+        ClientAddrConfiguration::from_ips(
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            target_port,
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            0,
+        ),
+        CertificateVerificationMode::SkipVerification,
+    );
+    client_app.world_mut().flush();
+
+    let mut visited_states = Vec::new();
+    while client_app.world().contains_resource::<PendingHandoff>() {
+        client_app.update();
+        source_app.update();
+        target_app.update();
+        visited_states.push(**client_app.world().resource::<State<ClientState>>());
+    }
+    let completed = client_app.world().resource::<Messages<HandoffCompleted>>();
+    assert_eq!(completed.len(), 1);
+
+    for _ in 0..2 {
+        client_app.update();
+        visited_states.push(**client_app.world().resource::<State<ClientState>>());
+    }
+    assert!(
+        !visited_states.contains(&ClientState::Disconnected),
+        "handoff shouldn't disconnect replicon"
+    );
+    assert_eq!(visited_states.last(), Some(&ClientState::Connected));
+
+    client_app.world_mut().write_message(Test);
+    client_app.update();
+
+    let active = client_app.world().resource::<ActiveConnection>();
+    let quinnet_client = client_app.world().resource::<QuinnetClient>();
+    let client_id = quinnet_client
+        .get_connection_by_id(active.get().unwrap())
+        .unwrap()
+        .client_id()
+        .unwrap();
+    server_wait_for_message(&mut target_app, client_id);
+
+    let client_messages = target_app.world().resource::<Messages<FromClient<Test>>>();
+    assert_eq!(client_messages.len(), 1);
+}
+
+#[test]
+fn reconnect() {
+    let port = 6019; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins.set(RepliconQuinnetClientPlugin {
+                reconnect_policy: Some(ReconnectPolicy {
+                    initial_delay: Duration::ZERO,
+                    jitter: 0.0,
+                    ..Default::default()
+                }),
+            }),
+        ))
+        .finish();
+    }
+
+    setup_server(&mut server_app, port);
+    client_app.world_mut().commands().connect_replicon(
+        ClientAddrConfiguration::from_ips(
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            port,
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            0,
+        ),
+        CertificateVerificationMode::SkipVerification,
+    );
+    client_app.world_mut().flush();
+    wait_for_connection(&mut server_app, &mut client_app);
+
+    // Restart the server to drop the connection.
+    server_app
+        .world_mut()
+        .resource_mut::<QuinnetServer>()
+        .stop_endpoint()
+        .unwrap();
+    server_app.update();
+    setup_server(&mut server_app, port);
+
+    let mut progress = Vec::new();
+    while !progress
+        .iter()
+        .any(|step| matches!(step, ReconnectProgress::Succeeded { .. }))
+    {
+        sleep(Duration::from_secs_f32(0.01));
+        client_app.update();
+        server_app.update();
+        let messages = client_app.world().resource::<Messages<ReconnectProgress>>();
+        progress.extend(messages.iter_current_update_messages().copied());
+        assert!(
+            !progress
+                .iter()
+                .any(|step| matches!(step, ReconnectProgress::GaveUp { .. })),
+            "reconnection shouldn't give up: {progress:?}"
+        );
+    }
+    assert_eq!(
+        progress[0],
+        ReconnectProgress::Scheduled {
+            attempt: 1,
+            delay: Duration::ZERO
+        }
+    );
+
+    client_app.update();
+    let client_state = client_app.world().resource::<State<ClientState>>();
+    assert_eq!(*client_state, ClientState::Connected);
+    assert!(!client_app.world().contains_resource::<Reconnecting>());
+}
+
+#[test]
+fn disconnect_reason() {
+    let port = 6020; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+    server_app
+        .world_mut()
+        .write_message(DisconnectRequest { client });
+    server_app.update();
+
+    let disconnects = server_app
+        .world()
+        .resource::<Messages<ClientDisconnected>>();
+    let disconnect = disconnects.iter_current_update_messages().next().unwrap();
+    assert_eq!(disconnect.client, client);
+    assert_eq!(disconnect.reason, DisconnectReason::Local);
+
+    loop {
+        sleep(Duration::from_secs_f32(0.05));
+        server_app.update();
+        client_app.update();
+        let client_state = client_app.world().resource::<State<ClientState>>();
+        if *client_state == ClientState::Disconnected {
+            break;
+        }
+    }
+
+    let disconnects = client_app
+        .world()
+        .resource::<Messages<ServerDisconnected>>();
+    assert_eq!(disconnects.len(), 1);
+    let disconnect = disconnects.iter_current_update_messages().next().unwrap();
+    assert!(
+        matches!(disconnect.reason, DisconnectReason::Closed { code: 0, .. }),
+        "the server closed the connection without a code, but got {:?}",
+        disconnect.reason
+    );
+}
+
+#[test]
+fn client_disconnect_reason() {
+    let port = 6026; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    let mut quinnet_client = client_app.world_mut().resource_mut::<QuinnetClient>();
+    let default_connection = quinnet_client.get_default_connection().unwrap();
+    quinnet_client.close_connection(default_connection).unwrap();
+    client_app.update();
+
+    let disconnects = client_app
+        .world()
+        .resource::<Messages<ServerDisconnected>>();
+    let disconnect = disconnects.iter_current_update_messages().next().unwrap();
+    assert_eq!(disconnect.reason, DisconnectReason::Local);
+
+    let reason = loop {
+        sleep(Duration::from_secs_f32(0.05));
+        server_app.update();
+        let disconnects = server_app
+            .world()
+            .resource::<Messages<ClientDisconnected>>();
+        if let Some(disconnect) = disconnects.iter_current_update_messages().next() {
+            break disconnect.reason.clone();
+        }
+    };
+    assert!(
+        matches!(reason, DisconnectReason::Closed { code: 0, .. }),
+        "the client closed the connection without a code, but got {reason:?}"
+    );
+}
+
+#[test]
+fn disconnect_reason_codes() {
+    let reason = |code: u64| {
+        let error = ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: VarInt::from_u64(code).unwrap(),
+            reason: Bytes::from_static(b"reason"),
+        });
+        DisconnectReason::from(&error)
+    };
+
+    assert_eq!(
+        reason(0),
+        DisconnectReason::Closed {
+            code: 0,
+            reason: "reason".into()
+        }
+    );
+    assert_eq!(
+        reason(SERVER_SHUTDOWN_CODE.into()),
+        DisconnectReason::ServerShutdown
+    );
+    for code in [KICKED_CODE, BANNED_CODE, REJECTED_CODE, *KICK_CODES.end()] {
+        assert_eq!(
+            reason(code.into()),
+            DisconnectReason::Kicked {
+                code: code.into(),
+                reason: "reason".into()
+            }
+        );
+    }
+    let custom = u64::from(*KICK_CODES.end()) + 1;
+    assert_eq!(
+        reason(custom),
+        DisconnectReason::Closed {
+            code: custom,
+            reason: "reason".into()
+        },
+        "application codes outside of the kick range shouldn't be reported as kicks"
+    );
+}
+
+#[test]
+fn quinnet_send_errors() {
+    assert_eq!(
+        QuinnetSendError::from(ConnectionSendError::ChannelClosed),
+        QuinnetSendError::ChannelClosed
+    );
+    assert_eq!(
+        QuinnetSendError::from(ConnectionSendError::ConnectionClosed),
+        QuinnetSendError::ConnectionClosed
+    );
+}
+
+#[test]
+fn reconnect_base_delay() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(5),
+        multiplier: 2.0,
+        ..Default::default()
+    };
+    assert_eq!(policy.base_delay(1), Duration::from_secs(1));
+    assert_eq!(policy.base_delay(3), Duration::from_secs(4));
+    assert_eq!(policy.base_delay(10), Duration::from_secs(5));
+
+    let policy = ReconnectPolicy {
+        multiplier: -2.0,
+        ..policy
+    };
+    assert_eq!(
+        policy.base_delay(2),
+        Duration::ZERO,
+        "negative delays should be clamped"
+    );
+}
+
+#[test]
+fn shutdown_server() {
+    let port = 6021; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .add_server_message::<Test>(Channel::Ordered)
+        .finish();
+    }
+
+    setup(&mut server_app, &mut client_app, port);
+
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
+    server_app
+        .world_mut()
+        .write_message(ShutdownServer::default());
+
+    let mut received = 0;
+    let mut reasons = Vec::new();
+    while *client_app.world().resource::<State<ClientState>>() != ClientState::Disconnected {
+        sleep(Duration::from_secs_f32(0.01));
+        server_app.update();
+        client_app.update();
+        let messages = client_app.world().resource::<Messages<Test>>();
+        received += messages.iter_current_update_messages().count();
+        let disconnects = client_app
+            .world()
+            .resource::<Messages<ServerDisconnected>>();
+        reasons.extend(
+            disconnects
+                .iter_current_update_messages()
+                .map(|disconnect| disconnect.reason.clone()),
+        );
+    }
+    assert_eq!(received, 1, "pending message should be delivered");
+    assert_eq!(reasons, [DisconnectReason::ServerShutdown]);
+
+    server_app.update();
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Stopped);
+}
+
+#[test]
+fn restart_after_shutdown() {
+    let port = 6030; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_apps = [App::new(), App::new()];
+    for app in [&mut server_app].into_iter().chain(client_apps.iter_mut()) {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
+
+    let [first_app, second_app] = &mut client_apps;
+    setup(&mut server_app, first_app, port);
+
+    server_app
+        .world_mut()
+        .write_message(ShutdownServer::default());
+    server_app.update();
+    assert!(server_app.world().contains_resource::<ShuttingDown>());
+
+    // Stop the endpoint before the shutdown completes.
+    server_app
+        .world_mut()
+        .resource_mut::<QuinnetServer>()
+        .stop_endpoint()
+        .unwrap();
+    server_app.update();
+    assert!(
+        !server_app.world().contains_resource::<ShuttingDown>(),
+        "shutdown should end with the endpoint"
+    );
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Stopped);
+
+    setup(&mut server_app, second_app, port);
+    for _ in 0..5 {
+        sleep(Duration::from_secs_f32(0.05));
+        server_app.update();
+        second_app.update();
+    }
+
+    let server_state = server_app.world().resource::<State<ServerState>>();
+    assert_eq!(*server_state, ServerState::Running);
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    assert_eq!(
+        clients.iter(server_app.world()).len(),
+        1,
+        "restarted server should accept connections"
+    );
+}
+
+#[test]
+fn kick_client() {
+    let port = 6022; // TODO Use port 0 and retrieve the port used by the server.
     let mut server_app = App::new();
     let mut client_app = App::new();
     for app in [&mut server_app, &mut client_app] {
@@ -35,65 +1319,175 @@ fn connect_disconnect() {
             }),
             RepliconQuinnetPlugins,
         ))
+        .add_server_message::<Test>(Channel::Ordered)
         .finish();
     }
 
     setup(&mut server_app, &mut client_app, port);
 
-    let server_state = server_app.world().resource::<State<ServerState>>();
-    assert_eq!(*server_state, ServerState::Running);
-
-    let quinnet_server = server_app.world().resource::<QuinnetServer>();
-    assert_eq!(quinnet_server.endpoint().clients().len(), 1);
-
-    // // TODO Better way to wait a bit more for `AuthorizedClient`component insertion. Maybe wait on `ProtocolHash` event ?
-    sleep(Duration::from_secs_f32(0.05));
-    server_app.update();
-
     let mut clients = server_app
         .world_mut()
-        .query::<(&ConnectedClient, &AuthorizedClient)>();
-    assert_eq!(clients.iter(server_app.world()).len(), 1);
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
+    server_app
+        .world_mut()
+        .write_message(KickClient::new(client, "cheating").with_code(42));
+    // Other disconnections in the same frame shouldn't panic or override the first kick.
+    server_app
+        .world_mut()
+        .write_message(KickClient::new(client, "again"));
+    server_app
+        .world_mut()
+        .write_message(DisconnectRequest { client });
+    server_app.update();
 
-    let client_state = client_app.world().resource::<State<ClientState>>();
-    assert_eq!(*client_state, ClientState::Connected);
+    assert_eq!(clients.iter(server_app.world()).len(), 0);
 
-    let mut quinnet_client = client_app.world_mut().resource_mut::<QuinnetClient>();
-    assert!(quinnet_client.is_connected());
+    let mut received = 0;
+    let mut reasons = Vec::new();
+    while *client_app.world().resource::<State<ClientState>>() != ClientState::Disconnected {
+        sleep(Duration::from_secs_f32(0.01));
+        server_app.update();
+        client_app.update();
+        let messages = client_app.world().resource::<Messages<Test>>();
+        received += messages.iter_current_update_messages().count();
+        let disconnects = client_app
+            .world()
+            .resource::<Messages<ServerDisconnected>>();
+        reasons.extend(
+            disconnects
+                .iter_current_update_messages()
+                .map(|disconnect| disconnect.reason.clone()),
+        );
+    }
+    assert_eq!(
+        received, 1,
+        "message sent before the kick should be received"
+    );
+    assert_eq!(
+        reasons,
+        [DisconnectReason::Kicked {
+            code: 42,
+            reason: "cheating".into()
+        }]
+    );
+}
 
-    let default_connection = quinnet_client.get_default_connection().unwrap();
-    quinnet_client.close_connection(default_connection).unwrap();
+#[test]
+fn ban_list() {
+    let port = 6023; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
 
-    client_app.update();
+    let mut ban_list = BanList::default();
+    assert!(ban_list.ban(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    server_app.insert_resource(ban_list);
 
-    server_wait_for_disconnect(&mut server_app);
+    setup_server(&mut server_app, port);
+    setup_client(&mut client_app, port);
+
+    let reason = client_wait_for_disconnect(&mut server_app, &mut client_app);
+    assert_eq!(
+        reason,
+        DisconnectReason::Kicked {
+            code: BANNED_CODE.into(),
+            reason: "banned".into()
+        }
+    );
 
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
     assert_eq!(clients.iter(server_app.world()).len(), 0);
+}
 
-    let client_state = client_app.world().resource::<State<ClientState>>();
-    assert_eq!(*client_state, ClientState::Disconnected);
+#[test]
+fn admission_control() {
+    let port = 6024; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_apps = [App::new(), App::new(), App::new()];
+    for app in [&mut server_app].into_iter().chain(client_apps.iter_mut()) {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
 
-    let mut quinnet_server = server_app.world_mut().resource_mut::<QuinnetServer>();
-    assert_eq!(quinnet_server.endpoint().clients().len(), 0);
+    server_app.insert_resource(
+        AdmissionControl {
+            max_clients: Some(2),
+            ..Default::default()
+        }
+        .with_filter(|request| {
+            if request.clients_from_ip > 0 {
+                Err(AdmissionRejection::new("already connected").with_code(42))
+            } else {
+                Ok(())
+            }
+        }),
+    );
 
-    quinnet_server.stop_endpoint().unwrap();
+    let [first_app, second_app, third_app] = &mut client_apps;
+    setup(&mut server_app, first_app, port);
 
-    server_app.update();
+    setup_client(second_app, port);
+    let reason = client_wait_for_disconnect(&mut server_app, second_app);
+    assert_eq!(
+        reason,
+        DisconnectReason::Kicked {
+            code: 42,
+            reason: "already connected".into()
+        }
+    );
 
-    let server_state = server_app.world().resource::<State<ServerState>>();
+    server_app
+        .world_mut()
+        .resource_mut::<AdmissionControl>()
+        .max_clients = Some(1);
+    setup_client(third_app, port);
+    let reason = client_wait_for_disconnect(&mut server_app, third_app);
     assert_eq!(
-        *server_state,
-        ServerState::Stopped,
-        "requires resource removal"
+        reason,
+        DisconnectReason::Kicked {
+            code: REJECTED_CODE.into(),
+            reason: "server full".into()
+        }
     );
+
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    assert_eq!(clients.iter(server_app.world()).len(), 1);
 }
 
 #[test]
-fn disconnect_request() {
-    let port = 6001; // TODO Use port 0 and retrieve the port used by the server.
+fn auth_handshake() {
+    let port = 6025; // TODO Use port 0 and retrieve the port used by the server.
     let mut server_app = App::new();
-    let mut client_app = App::new();
-    for app in [&mut server_app, &mut client_app] {
+    let mut client_apps = [App::new(), App::new()];
+    for app in [&mut server_app].into_iter().chain(client_apps.iter_mut()) {
         app.add_plugins((
             MinimalPlugins,
             StatesPlugin,
@@ -107,53 +1501,85 @@ fn disconnect_request() {
         .finish();
     }
 
-    setup(&mut server_app, &mut client_app, port);
+    server_app.insert_resource(ServerAuth::new(|request| {
+        if request.response == [request.challenge, b"ticket"].concat() {
+            Ok(())
+        } else {
+            Err(AdmissionRejection::new("invalid ticket"))
+        }
+    }));
 
-    // TODO (Pending messages delivery on disconnect) Currently, disconnecting does not deliver pending messages reliably enough to be tested.
-    // If we wanted to test this, we'd need not to drop the InternalConnectionRef immediately in Quinnet when disconnecting a client from the server.
+    let [valid_app, invalid_app] = &mut client_apps;
+    valid_app.insert_resource(ClientAuth::new(|challenge| [challenge, b"ticket"].concat()));
+    invalid_app.insert_resource(ClientAuth::ticket(&b"ticket"[..]));
 
-    server_app.world_mut().spawn(Replicated);
-    server_app.world_mut().write_message(ToClients {
-        mode: SendMode::Broadcast,
-        message: Test,
-    });
+    setup_server(&mut server_app, port);
+    setup_client(valid_app, port);
+    while *valid_app.world().resource::<State<ClientState>>() != ClientState::Connected {
+        sleep(Duration::from_secs_f32(0.01));
+        valid_app.update();
+        server_app.update();
+    }
 
     let mut clients = server_app
         .world_mut()
         .query_filtered::<Entity, With<ConnectedClient>>();
-    let client = clients.single(server_app.world()).unwrap();
-    server_app
-        .world_mut()
-        .write_message(DisconnectRequest { client });
+    assert_eq!(clients.iter(server_app.world()).len(), 1);
 
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
     server_app.update();
+    client_wait_for_message(valid_app);
+    valid_app.update();
+    let messages = valid_app.world().resource::<Messages<Test>>();
+    assert_eq!(messages.len(), 1);
 
-    assert_eq!(clients.iter(server_app.world()).len(), 0);
-
-    // TODO Better way to wait for disconnect propagation
-    sleep(Duration::from_secs_f32(0.05));
-
-    server_app.update();
-    client_app.update();
+    setup_client(invalid_app, port);
+    let reason = client_wait_for_disconnect(&mut server_app, invalid_app);
+    assert_eq!(
+        reason,
+        DisconnectReason::Kicked {
+            code: REJECTED_CODE.into(),
+            reason: "invalid ticket".into()
+        }
+    );
+    assert_eq!(clients.iter(server_app.world()).len(), 1);
+}
 
-    let client_state = client_app.world().resource::<State<ClientState>>();
-    assert_eq!(*client_state, ClientState::Disconnected);
+#[test]
+fn client_auth_timeout() {
+    let port = 6028; // TODO Use port 0 and retrieve the port used by the server.
+    let mut server_app = App::new();
+    let mut client_app = App::new();
+    for app in [&mut server_app, &mut client_app] {
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            RepliconPlugins.set(ServerPlugin {
+                tick_schedule: PostUpdate.intern(),
+                ..Default::default()
+            }),
+            RepliconQuinnetPlugins,
+        ))
+        .finish();
+    }
 
-    // TODO (Pending messages delivery on disconnect)
-    // let events = client_app.world().resource::<Events<TestEvent>>();
-    // assert_eq!(events.len(), 1, "last event should be received");
+    // The server doesn't require authentication and never accepts the client.
+    client_app.insert_resource(
+        ClientAuth::ticket(&b"ticket"[..]).with_timeout(Duration::from_millis(100)),
+    );
 
-    // let mut replicated = client_app.world_mut().query::<&Replicated>();
-    // assert_eq!(
-    //     replicated.iter(client_app.world()).len(),
-    //     1,
-    //     "last replication should be received"
-    // );
+    setup_server(&mut server_app, port);
+    setup_client(&mut client_app, port);
+    let reason = client_wait_for_disconnect(&mut server_app, &mut client_app);
+    assert_eq!(reason, DisconnectReason::AuthTimedOut);
 }
 
 #[test]
-fn replication() {
-    let port = 6002; // TODO Use port 0 and retrieve the port used by the server.
+fn transport_diagnostics() {
+    let port = 6010; // TODO Use port 0 and retrieve the port used by the server.
     let mut server_app = App::new();
     let mut client_app = App::new();
     for app in [&mut server_app, &mut client_app] {
@@ -166,23 +1592,43 @@ fn replication() {
             }),
             RepliconQuinnetPlugins,
         ))
+        .add_client_message::<Test>(Channel::Ordered)
         .finish();
     }
 
     setup(&mut server_app, &mut client_app, port);
 
-    server_app.world_mut().spawn(Replicated);
+    client_app.world_mut().write_message(Test);
+    client_app.update();
 
-    server_app.update();
-    client_wait_for_message(&mut client_app);
+    let store = client_app.world().resource::<DiagnosticsStore>();
+    let rtt = store
+        .get_measurement(&RepliconQuinnetClientPlugin::RTT)
+        .unwrap();
+    assert!(rtt.value > 0.);
+    let channel_id = client_app
+        .world()
+        .resource::<RepliconChannels>()
+        .client_channels()
+        .len()
+        - 1;
+    let sent_messages = store
+        .get_measurement(&RepliconQuinnetClientPlugin::channel_sent_messages(
+            channel_id,
+        ))
+        .unwrap();
+    assert_eq!(sent_messages.value, 1.);
 
-    let mut replicated = client_app.world_mut().query::<&Replicated>();
-    assert_eq!(replicated.iter(client_app.world()).len(), 1);
+    let store = server_app.world().resource::<DiagnosticsStore>();
+    let connected_clients = store
+        .get_measurement(&RepliconQuinnetServerPlugin::CONNECTED_CLIENTS)
+        .unwrap();
+    assert_eq!(connected_clients.value, 1.);
 }
 
 #[test]
-fn server_event() {
-    let port = 6003; // TODO Use port 0 and retrieve the port used by the server.
+fn vanished_client_messages() {
+    let port = 6011; // TODO Use port 0 and retrieve the port used by the server.
     let mut server_app = App::new();
     let mut client_app = App::new();
     for app in [&mut server_app, &mut client_app] {
@@ -201,21 +1647,27 @@ fn server_event() {
 
     setup(&mut server_app, &mut client_app, port);
 
-    server_app.world_mut().write_message(ToClients {
-        mode: SendMode::Broadcast,
-        message: Test,
-    });
+    let mut clients = server_app
+        .world_mut()
+        .query_filtered::<Entity, With<ConnectedClient>>();
+    let client = clients.single(server_app.world()).unwrap();
+
+    // Simulate a client despawned after replicon wrote its messages.
+    server_app.world_mut().despawn(client);
+    server_app
+        .world_mut()
+        .resource_mut::<ServerMessages>()
+        .send(client, 0, vec![0]);
 
     server_app.update();
-    client_wait_for_message(&mut client_app);
 
-    let messages = client_app.world().resource::<Messages<Test>>();
-    assert_eq!(messages.len(), 1);
+    let dropped_messages = server_app.world().resource::<DroppedMessages>();
+    assert_eq!(dropped_messages.vanished_clients, 1);
 }
 
 #[test]
-fn client_event() {
-    let port = 6004; // TODO Use port 0 and retrieve the port used by the server.
+fn multiplexed_channels() {
+    let port = 6012; // TODO Use port 0 and retrieve the port used by the server.
     let mut server_app = App::new();
     let mut client_app = App::new();
     for app in [&mut server_app, &mut client_app] {
@@ -227,25 +1679,116 @@ fn client_event() {
                 ..Default::default()
             }),
             RepliconQuinnetPlugins,
-        ))
-        .add_client_message::<Test>(Channel::Ordered)
-        .finish();
+        ));
+        // More channels than Quinnet supports.
+        let mut channels = app.world_mut().resource_mut::<RepliconChannels>();
+        for _ in 0..300 {
+            channels.create_server_channel(Channel::Ordered);
+            channels.create_client_channel(Channel::Ordered);
+        }
+        app.add_server_message::<Test>(Channel::Ordered)
+            .add_client_message::<Test>(Channel::Ordered)
+            .finish();
     }
 
     let client_id = setup(&mut server_app, &mut client_app, port);
 
-    let server_state = server_app.world().resource::<State<ServerState>>();
-    assert_eq!(*server_state, ServerState::Running);
-
+    server_app.world_mut().write_message(ToClients {
+        mode: SendMode::Broadcast,
+        message: Test,
+    });
     client_app.world_mut().write_message(Test);
 
+    server_app.update();
+    client_wait_for_message(&mut client_app);
     client_app.update();
     server_wait_for_message(&mut server_app, client_id);
 
+    let messages = client_app.world().resource::<Messages<Test>>();
+    assert_eq!(messages.len(), 1);
     let client_messages = server_app.world().resource::<Messages<FromClient<Test>>>();
     assert_eq!(client_messages.len(), 1);
 }
 
+#[test]
+fn channel_config_errors() {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        StatesPlugin,
+        RepliconPlugins,
+        RepliconQuinnetPlugins,
+    ))
+    .finish();
+
+    let channels = app.world().resource::<RepliconChannels>();
+    assert!(channels.try_server_configs(1024).is_ok());
+    assert!(matches!(
+        channels.try_server_configs(0),
+        Err(ChannelConfigError::PayloadSizeOutOfRange { size: 0 })
+    ));
+    assert!(matches!(
+        channels.try_client_configs(0),
+        Err(ChannelConfigError::PayloadSizeOutOfRange { size: 0 })
+    ));
+
+    let too_many = ChannelMapping::new(&[Channel::Ordered; MAX_REPLICON_CHANNELS + 1]);
+    assert!(matches!(
+        too_many.quinnet_configs(1024),
+        Err(ChannelConfigError::TooManyChannels { count }) if count == MAX_REPLICON_CHANNELS + 1
+    ));
+
+    let multiplexed = ChannelMapping::new(&[Channel::Ordered; MAX_QUINNET_CHANNELS + 1]);
+    assert!(multiplexed.is_multiplexed());
+    assert!(multiplexed.quinnet_configs(1024).is_ok());
+    assert!(matches!(
+        multiplexed.quinnet_configs(MAX_RELIABLE_FRAME_SIZE),
+        Err(ChannelConfigError::IncompatibleFrameSize { .. })
+    ));
+}
+
+#[test]
+fn channel_overrides() {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        StatesPlugin,
+        RepliconPlugins,
+        RepliconQuinnetPlugins,
+    ))
+    .add_server_message::<Test>(Channel::Ordered)
+    .insert_resource(
+        QuinnetChannelOverrides::default()
+            .server_message::<Test>(ChannelOverride::default().with_max_frame_size(512)),
+    )
+    .finish();
+
+    let channel_id = app
+        .world()
+        .resource::<RepliconChannels>()
+        .server_channels()
+        .len()
+        - 1;
+    let mappings = app.world().resource::<ChannelMappings>();
+    let configs = mappings.server().quinnet_configs(1024).unwrap();
+    assert!(matches!(
+        configs.configs()[channel_id],
+        ChannelConfig::OrderedReliable {
+            max_frame_size: 512
+        }
+    ));
+    let configs = mappings.server_configs();
+    assert!(
+        matches!(
+            configs.configs()[channel_id],
+            ChannelConfig::OrderedReliable {
+                max_frame_size: 512
+            }
+        ),
+        "configs built by hand from the mappings should keep the overrides"
+    );
+}
+
 fn setup(
     server_app: &mut App,
     client_app: &mut App,
@@ -256,8 +1799,8 @@ fn setup(
     wait_for_connection(server_app, client_app)
 }
 
-fn setup_client(app: &mut App, server_port: u16) {
-    let send_channels_cfg = app.world().resource::<RepliconChannels>().client_configs();
+fn setup_client(app: &mut App, server_port: u16) -> ConnectionLocalId {
+    let send_channels_cfg = app.world().resource::<ChannelMappings>().client_configs();
 
     let mut client = app.world_mut().resource_mut::<QuinnetClient>();
     client
@@ -271,11 +1814,11 @@ fn setup_client(app: &mut App, server_port: u16) {
             cert_mode: CertificateVerificationMode::SkipVerification,
             defaultables: ClientConnectionConfigurationDefaultables { send_channels_cfg },
         })
-        .unwrap();
+        .unwrap()
 }
 
 fn setup_server(app: &mut App, server_port: u16) {
-    let send_channels_cfg = app.world().resource::<RepliconChannels>().server_configs();
+    let send_channels_cfg = app.world().resource::<ChannelMappings>().server_configs();
 
     let mut server = app.world_mut().resource_mut::<QuinnetServer>();
     server
@@ -353,6 +1896,20 @@ fn server_wait_for_message(server_app: &mut App, client_id: bevy_quinnet::shared
     }
 }
 
+fn client_wait_for_disconnect(server_app: &mut App, client_app: &mut App) -> DisconnectReason {
+    loop {
+        sleep(Duration::from_secs_f32(0.01));
+        client_app.update();
+        server_app.update();
+        let disconnects = client_app
+            .world()
+            .resource::<Messages<ServerDisconnected>>();
+        if let Some(disconnect) = disconnects.iter_current_update_messages().next() {
+            return disconnect.reason.clone();
+        }
+    }
+}
+
 fn server_wait_for_disconnect(server_app: &mut App) {
     loop {
         sleep(Duration::from_secs_f32(0.05));
@@ -372,3 +1929,9 @@ fn server_wait_for_disconnect(server_app: &mut App) {
 
 #[derive(Deserialize, Message, Serialize)]
 struct Test;
+
+#[derive(Deserialize, Message, Serialize)]
+struct Blob(Vec<u8>);
+
+#[derive(Component)]
+struct Moved;